@@ -2,6 +2,7 @@ mod utils;
 mod render;
 mod math;
 
+use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -15,107 +16,840 @@ extern {
     fn alert(s: &str);
 }
 
+// render target pixels per side, more than browsers allow a canvas
+const MAX_TARGET_SIZE: u32 = 8192;
+
+// js passes texture sizes as i32, negative ones are as invalid as zero
+fn texture_from_js(data: Vec<u8>, width: i32, height: i32) -> Result<render::Texture, JsError> {
+    render::Texture::try_from_rgba(data, width.max(0) as u32, height.max(0) as u32).map_err(|e| JsError::new(&e.to_string()))
+}
+
 #[wasm_bindgen]
 pub struct CanvasRenderer {
     tick: u32,
-    width: u32,
-    height: u32,
-    buffer: Vec<u8>,
-    camera_pos: math::Vector3,
-    camera_tar: math::Vector3,
+    // css pixels of the canvas element
+    display_width: u32,
+    display_height: u32,
+    pixel_ratio: f32,
+    // color and depth, the display size times the pixel ratio. kept
+    // between frames along with the draw scratch buffers
+    target: render::RenderTarget,
+    scratch: render::scene::DrawScratch,
+    // the point the camera keeps facing as it moves, none once its
+    // orientation is set directly
+    camera_target: Option<math::Vector3>,
+    controls: render::controls::CameraControls,
     scene: render::scene::Scene,
     texture: render::Texture,
+    lenient_loading: bool,
+    load_warnings: Vec<String>,
+    // used for obj files without vn lines, crease angle in radians
+    normal_generation: render::NormalGeneration,
+    crease_angle: f32,
+    // external files referenced by the next model to load, keyed by uri
+    resources: HashMap<String, Vec<u8>>,
+    // reverses the faces of every model loaded from now on
+    flip_winding: bool,
+    stats: render::scene::FrameStats,
+}
+
+impl CanvasRenderer {
+    fn render_target(width: u32, height: u32) -> render::RenderTarget {
+        let width = width.clamp(1, MAX_TARGET_SIZE);
+        let height = height.clamp(1, MAX_TARGET_SIZE);
+        let pixels = width as usize * height as usize;
+        render::RenderTarget {
+            width,
+            height,
+            buffer: vec![0; pixels * 4],
+            depth: vec![1.0; pixels],
+        }
+    }
+
+    fn load_obj(&mut self, obj_contents: String) -> Result<render::scene::Object, JsError> {
+        self.load_warnings.clear();
+        let mut obj = if self.lenient_loading {
+            let (obj, warnings) = render::scene::ObjLoader::load_obj_lenient(obj_contents);
+            self.load_warnings = warnings.iter().map(|w| w.to_string()).collect();
+            obj
+        } else {
+            render::scene::ObjLoader::load_obj(obj_contents).map_err(|e| JsError::new(&e.to_string()))?
+        };
+        self.prepare_loaded(&mut obj);
+        Ok(obj)
+    }
+
+    // every loader leaves missing normals empty, they are flipped first so
+    // generated normals face out of the corrected winding
+    fn prepare_loaded(&self, obj: &mut render::scene::Object) {
+        if self.flip_winding {
+            obj.flip_winding();
+        }
+        if obj.vertex_normals.is_empty() {
+            obj.generate_normals(self.normal_generation, self.crease_angle);
+        }
+    }
+
+    // re-aims after the camera is moved from js, and lets the controls carry
+    // on from the new pose
+    fn aim_camera(&mut self) {
+        if let Some(target) = self.camera_target {
+            self.scene.camera.look_at(&target);
+        }
+        self.controls.sync(&self.scene.camera, self.camera_target);
+    }
 }
 
 #[wasm_bindgen]
 impl CanvasRenderer {
 
     pub fn width(&self) -> u32 {
-        self.width
+        self.target.width
     }
 
     pub fn height(&self) -> u32 {
-        self.height
+        self.target.height
     }
 
     pub fn buffer(&self) -> *const u8 {
-        self.buffer.as_ptr()
+        self.target.buffer.as_ptr()
     }
 
     pub fn new() -> CanvasRenderer {
+        CanvasRenderer::with_size(1024, 768)
+    }
+
+    pub fn with_size(width: u32, height: u32) -> CanvasRenderer {
         utils::set_panic_hook();
         let tick = 0u32;
-        let width = 1024;
-        let height = 768;
-        let buffer = vec![0; (width as usize)*(height as usize) * 4usize];
+        let width = width.max(1);
+        let height = height.max(1);
         let textdata = vec![0; (256 as usize)*(256 as usize) * 4usize];
         let scene = render::scene::Scene::new();
         CanvasRenderer {
             tick,
-            width,
-            height,
-            buffer,
-            camera_pos: math::Vector3{x: 0.0, y: 0.0, z: 1.0},
-            camera_tar: math::Vector3{x: 0.0, y: 0.0, z: 0.0},
+            display_width: width,
+            display_height: height,
+            pixel_ratio: 1.0,
+            target: CanvasRenderer::render_target(width, height),
+            scratch: render::scene::DrawScratch::default(),
+            camera_target: Some(math::Vector3::new()),
+            controls: render::controls::CameraControls::new(),
             scene,
-            texture: render::Texture{ data: textdata, width: 256, height: 256 },
+            texture: render::Texture::from_rgba(textdata, 256, 256),
+            lenient_loading: false,
+            load_warnings: vec![],
+            normal_generation: render::NormalGeneration::SmoothingGroups,
+            crease_angle: std::f32::consts::PI,
+            resources: HashMap::new(),
+            flip_winding: false,
+            stats: render::scene::FrameStats::default(),
         }
     }
 
+    // display size in css pixels, the buffer gets pixel_ratio times as many
+    // pixels each way. pass window.devicePixelRatio for sharp output, or less to
+    // trade resolution for speed. the buffer pointer changes, re-read it
+    pub fn resize(&mut self, display_width: u32, display_height: u32, pixel_ratio: f32) {
+        let pixel_ratio = if pixel_ratio > 0.0 { pixel_ratio } else { 1.0 };
+        self.display_width = display_width.max(1);
+        self.display_height = display_height.max(1);
+        self.pixel_ratio = pixel_ratio;
+        let width = ((self.display_width as f32 * pixel_ratio).round() as u32).max(1);
+        let height = ((self.display_height as f32 * pixel_ratio).round() as u32).max(1);
+        self.target = CanvasRenderer::render_target(width, height);
+    }
+
+    pub fn set_pixel_ratio(&mut self, pixel_ratio: f32) {
+        self.resize(self.display_width, self.display_height, pixel_ratio);
+    }
+
+    pub fn pixel_ratio(&self) -> f32 {
+        self.pixel_ratio
+    }
+
     pub fn set_camera_target(&mut self, x: f32, y: f32, z: f32) {
-        self.camera_tar = math::Vector3{x: x, y: y, z: z};
+        self.camera_target = Some(math::Vector3 { x, y, z });
+        self.aim_camera();
     }
 
     pub fn set_camera_position(&mut self, x: f32, y: f32, z: f32) {
-        self.camera_pos = math::Vector3{x: x, y: y, z: z};
+        self.scene.camera.position = math::Vector3 { x, y, z };
+        self.aim_camera();
     }
 
-    pub fn set_texture(&mut self, data: Vec<u8>, width: i32, height: i32) {
-        self.texture = render::Texture{data: data, width: width as u32, height: height as u32}
+    // the world direction kept at the top of the screen while following a target
+    pub fn set_camera_up(&mut self, x: f32, y: f32, z: f32) {
+        let up = math::Vector3 { x, y, z };
+        if up.len() > 0.0 {
+            self.scene.camera.up = up.normal();
+            self.aim_camera();
+        }
     }
 
-    pub fn render(&mut self) {
-        let target_buffer = self.buffer.clone();
+    // unit quaternion turning the camera's x right, y up, z forward axes into
+    // world space. stops following the target until set_camera_target is called
+    pub fn set_camera_orientation(&mut self, x: f32, y: f32, z: f32, w: f32) {
+        let camera = &mut self.scene.camera;
+        camera.orientation = math::Quaternion { x, y, z, w }.normal();
+        camera.up = camera.axes().1;
+        self.camera_target = None;
+        self.aim_camera();
+    }
+
+    // banks the camera around its view direction, positive degrees lift the right side
+    pub fn roll_camera(&mut self, degrees: f32) {
+        self.scene.camera.roll(degrees.to_radians());
+        self.aim_camera();
+    }
+
+    pub fn camera_position(&self) -> Vec<f32> {
+        let p = &self.scene.camera.position;
+        vec![p.x, p.y, p.z]
+    }
+
+    // x, y, z, w
+    pub fn camera_orientation(&self) -> Vec<f32> {
+        let q = &self.scene.camera.orientation;
+        vec![q.x, q.y, q.z, q.w]
+    }
+
+    // the top of the screen in world space, including any roll
+    pub fn camera_up(&self) -> Vec<f32> {
+        let up = self.scene.camera.axes().1;
+        vec![up.x, up.y, up.z]
+    }
+
+    // lets the renderer move the camera from forwarded input events. orbit
+    // circles the current camera target, the others start from the current pose
+    pub fn set_camera_control(&mut self, mode: render::CameraControl) {
+        self.controls.mode = mode;
+        self.controls.sync(&self.scene.camera, self.camera_target);
+    }
+
+    // damping is how many seconds motion takes to mostly settle, 0 for none.
+    // look speed is degrees per pixel dragged, move speed world units per second
+    pub fn set_control_speed(&mut self, damping: f32, look_speed: f32, move_speed: f32) {
+        self.controls.damping = damping.max(0.0);
+        self.controls.look_speed = look_speed.to_radians();
+        self.controls.move_speed = move_speed.max(0.0);
+    }
 
-        let mut current_target = render::RenderTarget{
-            width: self.width(),
-            height: self.height(),
-            buffer: target_buffer,
-            depth: std::vec::Vec::with_capacity((self.width() * self.height()) as usize), 
+    // how close and far the orbit camera gets, and how far below and above
+    // the target it can go in degrees
+    pub fn set_orbit_limits(&mut self, min_distance: f32, max_distance: f32, min_pitch: f32, max_pitch: f32) {
+        let controls = &mut self.controls;
+        controls.min_distance = min_distance.max(1e-4);
+        controls.max_distance = max_distance.max(controls.min_distance);
+        controls.min_pitch = min_pitch.clamp(-89.0, 89.0).to_radians();
+        controls.max_pitch = max_pitch.clamp(-89.0, 89.0).to_radians().max(controls.min_pitch);
+    }
+
+    // the pointer_* calls take offsetX / offsetY in css pixels and PointerEvent.button
+    pub fn pointer_down(&mut self, x: f32, y: f32, button: u32) {
+        self.controls.pointer_down(x, y, button);
+    }
+
+    pub fn pointer_move(&mut self, x: f32, y: f32) {
+        self.controls.pointer_move(x, y);
+    }
+
+    pub fn pointer_up(&mut self, button: u32) {
+        self.controls.pointer_up(button);
+    }
+
+    // WheelEvent.deltaY in pixels
+    pub fn wheel(&mut self, delta: f32) {
+        self.controls.wheel(delta);
+    }
+
+    // Touch.identifier and position in css pixels, for each changed touch
+    pub fn touch_start(&mut self, id: i32, x: f32, y: f32) {
+        self.controls.touch_start(id, x, y);
+    }
+
+    pub fn touch_move(&mut self, id: i32, x: f32, y: f32) {
+        self.controls.touch_move(id, x, y);
+    }
+
+    pub fn touch_end(&mut self, id: i32) {
+        self.controls.touch_end(id);
+    }
+
+    // KeyboardEvent.code, wasd or the arrows move, space and c go up and down, q and e roll
+    pub fn key_down(&mut self, code: &str) {
+        self.controls.key_down(code);
+    }
+
+    pub fn key_up(&mut self, code: &str) {
+        self.controls.key_up(code);
+    }
+
+    // moves the camera by the input since the last call, once per frame
+    // before render with the seconds since the previous frame
+    pub fn update_controls(&mut self, dt: f32) {
+        if self.controls.mode == render::CameraControl::None {
+            return;
+        }
+        self.controls.update(&mut self.scene.camera, dt, self.display_height as f32);
+        self.camera_target = self.controls.orbit_target();
+    }
+
+    // vertical field of vision in degrees
+    pub fn set_camera_perspective(&mut self, field_of_vision: f32) {
+        self.scene.camera.projection = render::ProjectionMode::Perspective;
+        self.scene.camera.field_of_vision = field_of_vision.clamp(1.0, 179.0);
+    }
+
+    // height of the world that fills the screen, the width follows the aspect ratio
+    pub fn set_camera_orthographic(&mut self, height: f32) {
+        self.scene.camera.projection = render::ProjectionMode::Orthographic;
+        if height > 0.0 {
+            self.scene.camera.orthographic_height = height;
+        }
+    }
+
+    // the window on the near plane the frustum passes through, in view space
+    // units. shifting it sideways gives stereo pairs, splitting it gives tiles
+    pub fn set_camera_off_axis(&mut self, left: f32, right: f32, bottom: f32, top: f32) {
+        self.scene.camera.projection = render::ProjectionMode::OffAxis;
+        if right != left && top != bottom {
+            self.scene.camera.window = [left, right, bottom, top];
+        }
+    }
+
+    // depth range drawn, near has to stay in front of the camera and before far
+    pub fn set_camera_clip_range(&mut self, near: f32, far: f32) {
+        let near = near.max(1e-4);
+        self.scene.camera.near = near;
+        self.scene.camera.far = far.max(near * 1.001);
+    }
+
+    pub fn set_texture(&mut self, data: Vec<u8>, width: i32, height: i32) -> Result<(), JsError> {
+        self.texture = texture_from_js(data, width, height)?;
+        Ok(())
+    }
+
+    pub fn set_perspective_correct(&mut self, enabled: bool) {
+        self.scene.interpolation = if enabled {
+            render::TextureInterpolation::PerspectiveCorrect
+        } else {
+            render::TextureInterpolation::Affine
         };
-        
-        // zero
-        current_target.depth.resize((self.width() * self.height()) as usize, 1.0);
+    }
+
+    pub fn set_texture_filter(&mut self, object: usize, filter: render::TextureFilter) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.filter = filter;
+        }
+    }
+
+    pub fn set_object_uv_source(&mut self, object: usize, source: render::UvSource) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.uv_source = source;
+        }
+    }
+
+    // six size x size rgba faces one after another, +x -x +y -y +z -z
+    pub fn add_cube_map(&mut self, faces: Vec<u8>, size: u32) -> Result<usize, JsError> {
+        let cube_map = render::cubemap::CubeMap::from_faces(&faces, size).map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(self.scene.add_cube_map(cube_map))
+    }
+
+    // a 2:1 latitude / longitude panorama, resampled into faces of face_size
+    pub fn add_equirectangular_map(&mut self, data: Vec<u8>, width: u32, height: u32, face_size: u32) -> Result<usize, JsError> {
+        let panorama = render::Texture::try_from_rgba(data, width, height).map_err(|e| JsError::new(&e.to_string()))?;
+        let cube_map = render::cubemap::CubeMap::from_equirectangular(&panorama, face_size).map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(self.scene.add_cube_map(cube_map))
+    }
+
+    // refractive_index only matters for refraction, 1.5 is about glass
+    pub fn set_object_environment(&mut self, object: usize, cube_map: usize, mapping: render::EnvironmentMapping, refractive_index: f32) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.cube_map = Some(cube_map);
+            obj.environment = mapping;
+            obj.refractive_index = refractive_index;
+        }
+    }
+
+    pub fn set_texture_wrap(&mut self, object: usize, wrap: render::TextureWrap) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.texture.wrap = wrap;
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_directional_light(&mut self, dx: f32, dy: f32, dz: f32, r: f32, g: f32, b: f32, intensity: f32) -> usize {
+        self.scene.lights.push(render::scene::Light::directional(
+            math::Vector3{x: dx, y: dy, z: dz},
+            math::Vector3{x: r, y: g, z: b},
+            intensity));
+        self.scene.lights.len() - 1
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_point_light(&mut self, x: f32, y: f32, z: f32, r: f32, g: f32, b: f32, intensity: f32, range: f32) -> usize {
+        self.scene.lights.push(render::scene::Light::point(
+            math::Vector3{x, y, z},
+            math::Vector3{x: r, y: g, z: b},
+            intensity,
+            range));
+        self.scene.lights.len() - 1
+    }
+
+    // cone angles are in degrees
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_spot_light(&mut self, x: f32, y: f32, z: f32, dx: f32, dy: f32, dz: f32, r: f32, g: f32, b: f32, intensity: f32, range: f32, inner_angle: f32, outer_angle: f32) -> usize {
+        self.scene.lights.push(render::scene::Light::spot(
+            math::Vector3{x, y, z},
+            math::Vector3{x: dx, y: dy, z: dz},
+            math::Vector3{x: r, y: g, z: b},
+            intensity,
+            range,
+            inner_angle.to_radians(),
+            outer_angle.to_radians()));
+        self.scene.lights.len() - 1
+    }
+
+    pub fn set_light_position(&mut self, light: usize, x: f32, y: f32, z: f32) {
+        if let Some(l) = self.scene.lights.get_mut(light) {
+            l.position = math::Vector3{x, y, z};
+        }
+    }
+
+    pub fn set_light_direction(&mut self, light: usize, dx: f32, dy: f32, dz: f32) {
+        if let Some(l) = self.scene.lights.get_mut(light) {
+            l.direction = math::Vector3{x: dx, y: dy, z: dz}.normal();
+        }
+    }
+
+    pub fn set_light_color(&mut self, light: usize, r: f32, g: f32, b: f32, intensity: f32) {
+        if let Some(l) = self.scene.lights.get_mut(light) {
+            l.color = math::Vector3{x: r, y: g, z: b};
+            l.intensity = intensity;
+        }
+    }
+
+    pub fn clear_lights(&mut self) {
+        self.scene.lights.clear();
+    }
+
+    // colors in 0..1
+    pub fn set_background_color(&mut self, r: f32, g: f32, b: f32) {
+        self.scene.background = render::scene::Background::Solid(math::Vector3{x: r, y: g, z: b});
+    }
+
+    // fades from the top of the canvas to the bottom
+    pub fn set_background_gradient(&mut self, top_r: f32, top_g: f32, top_b: f32, bottom_r: f32, bottom_g: f32, bottom_b: f32) {
+        self.scene.background = render::scene::Background::Gradient {
+            top: math::Vector3{x: top_r, y: top_g, z: top_b},
+            bottom: math::Vector3{x: bottom_r, y: bottom_g, z: bottom_b},
+        };
+    }
 
-        // zero
-        for p in current_target.buffer.chunks_exact_mut(4) {
-            p[0] = 0;
-            p[1] = 0;
-            p[2] = 0;
-            p[3] = 255;
+    // a handle from add_cube_map or add_equirectangular_map
+    pub fn set_background_skybox(&mut self, cube_map: usize) {
+        self.scene.background = render::scene::Background::Skybox(cube_map);
+    }
+
+    pub fn set_ambient_light(&mut self, r: f32, g: f32, b: f32) {
+        self.scene.ambient = math::Vector3{x: r, y: g, z: b};
+    }
+
+    pub fn set_object_shading(&mut self, object: usize, shading: render::ShadingMode) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.shading = shading;
+        }
+    }
+
+    // alpha_cutoff is only used by AlphaTest. transparent modes are drawn after
+    // the opaque objects, sorted back to front
+    pub fn set_object_blend_mode(&mut self, object: usize, mode: render::BlendMode, alpha_cutoff: f32) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.blend_mode = mode;
+            obj.alpha_cutoff = alpha_cutoff;
+        }
+    }
+
+    // cuts away everything on the side the normal points away from,
+    // nx * x + ny * y + nz * z + d < 0 in world space
+    pub fn add_clip_plane(&mut self, nx: f32, ny: f32, nz: f32, d: f32) -> usize {
+        self.scene.clip_planes.push(math::Plane { normal: math::Vector3{x: nx, y: ny, z: nz}, d });
+        self.scene.clip_planes.len() - 1
+    }
+
+    pub fn clear_clip_planes(&mut self) {
+        self.scene.clip_planes.clear();
+    }
+
+    pub fn set_object_cull_mode(&mut self, object: usize, mode: render::CullMode) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.cull_mode = mode;
+        }
+    }
+
+    pub fn flip_object_winding(&mut self, object: usize) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.flip_winding();
+        }
+    }
+
+    // for models wound clockwise, applies to every loader
+    pub fn set_flip_winding_on_load(&mut self, flip: bool) {
+        self.flip_winding = flip;
+    }
+
+    // multiplied with the texture alpha of faces without a material
+    pub fn set_object_opacity(&mut self, object: usize, opacity: f32) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.opacity = opacity;
         }
+    }
+
+    pub fn set_object_specular(&mut self, object: usize, specular: f32, shininess: f32) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.specular = specular;
+            obj.shininess = shininess;
+        }
+    }
+
+    // object and triangle counts from the last render
+    pub fn frame_stats(&self) -> render::scene::FrameStats {
+        self.stats
+    }
+
+    pub fn render(&mut self) {
+        // the background covers every pixel, only depth needs clearing
+        self.target.depth.iter_mut().for_each(|d| *d = 1.0);
 
-        self.scene.camera.position = math::Vector3{ x: self.camera_pos.x, y: self.camera_pos.y, z: self.camera_pos.z };
-        self.scene.camera.target = math::Vector3{ x: self.camera_tar.x, y: self.camera_tar.y, z: self.camera_tar.z };
+        self.scene.draw_background(&mut self.target);
 
-        self.scene.draw(&mut current_target);
+        self.stats = self.scene.draw(&mut self.target, &mut self.scratch);
 
         {
-            let i = (((self.height * self.width / 2 ) + self.width() / 2) * 4u32) as usize;
-            current_target.buffer[i + 0] = 255;
-            current_target.buffer[i + 1] = 255;
-            current_target.buffer[i + 2] = 255;
-            current_target.buffer[i + 3] = 255;
+            let i = (((self.height() * self.width() / 2 ) + self.width() / 2) * 4u32) as usize;
+            self.target.buffer[i + 0] = 255;
+            self.target.buffer[i + 1] = 255;
+            self.target.buffer[i + 2] = 255;
+            self.target.buffer[i + 3] = 255;
         }
 
         self.tick += 1;
-        self.buffer = current_target.buffer;
     }
 
-    pub fn add_obj(&mut self, obj_contents: String, texture_data: Vec<u8>, texture_width: i32, texture_height: i32) {
-        let mut obj = render::scene::ObjLoader::load_obj(obj_contents);
-        obj.texture = render::Texture{data: texture_data, width: texture_width as u32, height: texture_height as u32};
+    // throws with the line and column of the first problem unless lenient loading is on
+    pub fn add_obj(&mut self, obj_contents: String, texture_data: Vec<u8>, texture_width: i32, texture_height: i32) -> Result<usize, JsError> {
+        let texture = texture_from_js(texture_data, texture_width, texture_height)?;
+        let mut obj = self.load_obj(obj_contents)?;
+        obj.texture = texture;
+
+        Ok(self.scene.add_object(obj))
+    }
+
+    // swaps the geometry, keeping texture, transform and hierarchy
+    pub fn replace_obj(&mut self, object: usize, obj_contents: String) -> Result<bool, JsError> {
+        if !self.scene.objects.contains(object) {
+            return Ok(false);
+        }
+        let loaded = self.load_obj(obj_contents)?;
+        let obj = &mut self.scene.objects[object];
+        obj.vertices = loaded.vertices;
+        obj.faces = loaded.faces;
+        obj.vertex_normals = loaded.vertex_normals;
+        obj.uvs = loaded.uvs;
+        obj.colors = loaded.colors;
+        // faces index the new usemtl list, materials already loaded from a .mtl are kept by name
+        let mut old_materials = std::mem::take(&mut obj.materials);
+        obj.materials = loaded.materials.into_iter()
+            .map(|m| match old_materials.iter().position(|old| old.name == m.name) {
+                Some(index) => old_materials.swap_remove(index),
+                None => m,
+            })
+            .collect();
+        obj.material_libs = loaded.material_libs;
+        obj.update_bounds();
+        obj.tangents.clear();
+        if obj.has_normal_maps() {
+            obj.generate_tangents();
+        }
+        Ok(true)
+    }
+
+    // with lenient loading malformed data is skipped and reported in load_warnings
+    pub fn set_lenient_loading(&mut self, enabled: bool) {
+        self.lenient_loading = enabled;
+    }
+
+    // warnings from the last lenient load
+    pub fn load_warnings(&self) -> Vec<String> {
+        self.load_warnings.clone()
+    }
+
+    // how normals are made for obj files without vn lines. faces bent by more than
+    // crease_angle (degrees) don't share normals
+    pub fn set_normal_generation(&mut self, mode: render::NormalGeneration, crease_angle: f32) {
+        self.normal_generation = mode;
+        self.crease_angle = crease_angle.to_radians();
+    }
+
+    // throws away the object's normals and builds new ones
+    pub fn generate_object_normals(&mut self, object: usize, mode: render::NormalGeneration, crease_angle: f32) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.generate_normals(mode, crease_angle.to_radians());
+        }
+    }
+
+    // uris of external buffers and images a .gltf needs, to be passed in with set_resource
+    pub fn gltf_resources(&self, data: Vec<u8>) -> Result<Vec<String>, JsError> {
+        render::gltf::GltfLoader::external_resources(&data).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    // loads .obj, .gltf, .glb, .stl or .ply data, the format is guessed from the contents.
+    // returns the handles of the added objects, several for a gltf scene
+    pub fn add_model(&mut self, data: Vec<u8>) -> Result<Vec<usize>, JsError> {
+        let to_js = |e: &dyn std::fmt::Display| JsError::new(&e.to_string());
+        let first = data.iter().find(|b| !b.is_ascii_whitespace()).cloned();
+
+        if render::gltf::is_glb(&data) || first == Some(b'{') {
+            return self.add_gltf(data);
+        }
+
+        let mut obj = if render::ply::PlyLoader::is_ply(&data) {
+            render::ply::PlyLoader::load_ply(&data).map_err(|e| to_js(&e))?
+        } else if render::stl::StlLoader::is_binary(&data) || render::stl::StlLoader::is_ascii(&data) {
+            render::stl::StlLoader::load_stl(&data).map_err(|e| to_js(&e))?
+        } else {
+            // load_obj already prepares it
+            let contents = String::from_utf8(data).map_err(|_| JsError::new("unrecognized model format"))?;
+            let mut obj = self.load_obj(contents)?;
+            obj.texture = render::Texture::blank();
+            return Ok(vec![self.scene.add_object(obj)]);
+        };
+        self.prepare_loaded(&mut obj);
+        Ok(vec![self.scene.add_object(obj)])
+    }
+
+    pub fn set_resource(&mut self, uri: String, data: Vec<u8>) {
+        self.resources.insert(uri, data);
+    }
+
+    // adds every node of a .gltf or .glb as an object, parented like in the file.
+    // returns the handles in node order, the resources are dropped afterwards
+    pub fn add_gltf(&mut self, data: Vec<u8>) -> Result<Vec<usize>, JsError> {
+        let loaded = render::gltf::GltfLoader::load_gltf(&data, &self.resources);
+        self.resources.clear();
+        let nodes = loaded.map_err(|e| JsError::new(&e.to_string()))?;
+
+        let mut handles: Vec<usize> = Vec::with_capacity(nodes.len());
+        for mut node in nodes {
+            self.prepare_loaded(&mut node.object);
+            let handle = self.scene.add_object(node.object);
+            if let Some(parent) = node.parent {
+                self.scene.set_parent(handle, Some(handles[parent]));
+            }
+            handles.push(handle);
+        }
+        Ok(handles)
+    }
+
+    pub fn remove_obj(&mut self, object: usize) -> bool {
+        self.scene.remove_object(object).is_some()
+    }
+
+    pub fn set_object_visible(&mut self, object: usize, visible: bool) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.visible = visible;
+        }
+    }
+
+    pub fn set_object_texture(&mut self, object: usize, texture_data: Vec<u8>, texture_width: i32, texture_height: i32) -> Result<(), JsError> {
+        let mut texture = texture_from_js(texture_data, texture_width, texture_height)?;
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            texture.wrap = obj.texture.wrap;
+            obj.texture = texture;
+        }
+        Ok(())
+    }
+
+    // tangent space normal map for faces without a material, needs uvs on the mesh
+    pub fn set_object_normal_map(&mut self, object: usize, texture_data: Vec<u8>, texture_width: i32, texture_height: i32) -> Result<(), JsError> {
+        let texture = texture_from_js(texture_data, texture_width, texture_height)?;
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.normal_map = Some(texture);
+            obj.generate_tangents();
+        }
+        Ok(())
+    }
+
+    pub fn clear_object_normal_map(&mut self, object: usize) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.normal_map = None;
+        }
+    }
+
+    pub fn object_vertex_count(&self, object: usize) -> Option<usize> {
+        self.scene.objects.get(object).map(|obj| obj.vertices.len())
+    }
+
+    pub fn object_face_count(&self, object: usize) -> Option<usize> {
+        self.scene.objects.get(object).map(|obj| obj.faces.len())
+    }
+
+    // .mtl file names referenced by the object's mtllib lines
+    pub fn object_material_libs(&self, object: usize) -> Vec<String> {
+        match self.scene.objects.get(object) {
+            Some(obj) => obj.material_libs.clone(),
+            None => vec![],
+        }
+    }
+
+    // returns the number of the object's materials defined by the file
+    pub fn load_mtl(&mut self, object: usize, mtl_contents: String) -> usize {
+        match self.scene.objects.get_mut(object) {
+            Some(obj) => obj.apply_materials(render::material::MtlLoader::load_mtl(&mtl_contents)),
+            None => 0,
+        }
+    }
+
+    // texture files the object's materials refer to that haven't been provided yet
+    pub fn object_texture_files(&self, object: usize) -> Vec<String> {
+        match self.scene.objects.get(object) {
+            Some(obj) => obj.missing_textures(),
+            None => vec![],
+        }
+    }
+
+    pub fn set_material_texture(&mut self, object: usize, file_name: String, texture_data: Vec<u8>, texture_width: i32, texture_height: i32) -> Result<usize, JsError> {
+        let texture = texture_from_js(texture_data, texture_width, texture_height)?;
+        Ok(match self.scene.objects.get_mut(object) {
+            Some(obj) => obj.set_texture_file(&file_name, &texture),
+            None => 0,
+        })
+    }
+
+    pub fn set_object_position(&mut self, object: usize, x: f32, y: f32, z: f32) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.transform.position = math::Vector3{x, y, z};
+        }
+    }
+
+    pub fn set_object_rotation(&mut self, object: usize, x: f32, y: f32, z: f32) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.transform.rotation = math::Vector3{x, y, z};
+        }
+    }
+
+    pub fn set_object_scale(&mut self, object: usize, x: f32, y: f32, z: f32) {
+        if let Some(obj) = self.scene.objects.get_mut(object) {
+            obj.transform.scale = math::Vector3{x, y, z};
+        }
+    }
+
+    pub fn set_object_parent(&mut self, object: usize, parent: Option<usize>) -> bool {
+        self.scene.set_parent(object, parent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize() {
+        let mut renderer = CanvasRenderer::with_size(40, 30);
+        assert_eq!((renderer.width(), renderer.height()), (40, 30));
+
+        renderer.resize(100, 50, 1.5);
+        assert_eq!((renderer.width(), renderer.height()), (150, 75));
+        assert_eq!(renderer.target.buffer.len(), 150 * 75 * 4);
+        assert_eq!(renderer.target.depth.len(), 150 * 75);
+        renderer.render();
+
+        renderer.set_pixel_ratio(0.5);
+        assert_eq!((renderer.width(), renderer.height()), (50, 25));
+        renderer.resize(0, 0, 0.0);
+        assert_eq!((renderer.width(), renderer.height(), renderer.pixel_ratio()), (1, 1, 1.0));
+        renderer.render();
+
+        // a huge display doesn't overflow the buffer size
+        renderer.resize(u32::MAX, 2, 4.0);
+        assert_eq!((renderer.width(), renderer.height()), (MAX_TARGET_SIZE, 8));
+        assert_eq!(renderer.target.depth.len(), MAX_TARGET_SIZE as usize * 8);
+    }
+
+    #[test]
+    fn test_flip_winding_on_load() {
+        let triangle = |face: &str| format!("v 0 0 0\nv 1 0 0\nv 0 1 0\nf {}\n", face);
+        let mut renderer = CanvasRenderer::with_size(4, 4);
+        let counter_clockwise = renderer.add_obj(triangle("1 2 3"), vec![255; 4], 1, 1).unwrap();
+        renderer.set_flip_winding_on_load(true);
+        let clockwise = renderer.add_obj(triangle("1 3 2"), vec![255; 4], 1, 1).unwrap();
+
+        // both face +z once the clockwise one is flipped
+        let objects = &renderer.scene.objects;
+        assert_eq!(objects[counter_clockwise].vertex_normals[0].z, 1.0);
+        assert_eq!(objects[clockwise].vertex_normals, objects[counter_clockwise].vertex_normals);
+    }
+
+    #[test]
+    fn test_flip_winding_on_load_ply_stl() {
+        let ply = |face: &str| format!("ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 {}\n", face);
+        let stl = |b: &str, c: &str| format!("solid t\nfacet normal 0 0 0\nouter loop\nvertex 0 0 0\nvertex {}\nvertex {}\nendloop\nendfacet\nendsolid t\n", b, c);
+        let mut renderer = CanvasRenderer::with_size(4, 4);
+        renderer.set_flip_winding_on_load(true);
+        let flipped = [
+            renderer.add_model(ply("0 2 1").into_bytes()).unwrap()[0],
+            renderer.add_model(stl("0 1 0", "1 0 0").into_bytes()).unwrap()[0],
+        ];
+
+        // generated after the flip, so they face +z like the corrected winding
+        for &handle in flipped.iter() {
+            let obj = &renderer.scene.objects[handle];
+            assert!(!obj.vertex_normals.is_empty());
+            assert!(obj.vertex_normals.iter().all(|n| (n.z - 1.0).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn test_replace_obj_materials() {
+        let triangle = "v -1 -1 -2\nv 1 -1 -2\nv 0 1 -2\n";
+        let mut renderer = CanvasRenderer::with_size(8, 8);
+        let handle = renderer.add_obj(format!("mtllib a.mtl\n{}usemtl red\nf 1 2 3\n", triangle), vec![255; 4], 1, 1).unwrap();
+        assert_eq!(renderer.load_mtl(handle, "newmtl red\nKd 1 0 0\n".to_string()), 1);
+
+        let replaced = format!("mtllib b.mtl\n{}usemtl blue\nf 1 2 3\nusemtl red\nf 1 3 2\n", triangle);
+        assert!(renderer.replace_obj(handle, replaced).unwrap());
+        let obj = &renderer.scene.objects[handle];
+        assert_eq!(obj.materials.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), vec!["blue", "red"]);
+        assert!(!obj.materials[0].defined && obj.materials[1].defined);
+        assert_eq!(obj.material_libs, vec!["b.mtl".to_string()]);
+
+        // the second material index used to be out of range
+        renderer.render();
+        assert!(renderer.frame_stats().triangles_drawn > 0);
+    }
+
+    #[test]
+    fn test_camera_controls() {
+        let mut renderer = CanvasRenderer::with_size(40, 30);
+        renderer.set_camera_position(0.0, 0.0, 4.0);
+        renderer.set_camera_target(0.0, 1.0, 0.0);
+        renderer.set_camera_control(render::CameraControl::Orbit);
+        renderer.set_control_speed(0.0, 0.1, 1.0);
+
+        // orbiting keeps the distance to the target set from js
+        renderer.pointer_down(10.0, 10.0, 0);
+        renderer.pointer_move(200.0, 60.0);
+        renderer.update_controls(0.016);
+        let p = renderer.camera_position();
+        let distance = (p[0] * p[0] + (p[1] - 1.0) * (p[1] - 1.0) + p[2] * p[2]).sqrt();
+        assert!((distance - 17.0f32.sqrt()).abs() < 1e-4);
 
-        self.scene.add_object(obj)
+        // moving the camera from js starts the orbit over from there
+        renderer.pointer_up(0);
+        renderer.set_camera_position(0.0, 1.0, 2.0);
+        renderer.update_controls(0.016);
+        let p = renderer.camera_position();
+        assert!(p[0].abs() < 1e-5 && (p[1] - 1.0).abs() < 1e-5 && (p[2] - 2.0).abs() < 1e-5);
     }
 }
@@ -1,5 +1,5 @@
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Point {
     pub x: f32,
     pub y: f32,
@@ -37,7 +37,7 @@ pub struct Vector2 {
   pub y: f32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vector3 {
   pub x: f32,
   pub y: f32,
@@ -87,7 +87,7 @@ impl Vector3 {
   pub fn cross(&self, v: &Vector3) -> Vector3 {
     Vector3 {
       x: self.y * v.z - self.z * v.y,
-      y: self.x * v.z - self.z * v.x,
+      y: self.z * v.x - self.x * v.z,
       z: self.x * v.y - self.y * v.x,
     }
   }
@@ -100,12 +100,46 @@ impl Vector3 {
     }
   }
 
+  pub fn add(&self, v: &Vector3) -> Vector3 {
+    Vector3 {
+      x: self.x + v.x,
+      y: self.y + v.y,
+      z: self.z + v.z,
+    }
+  }
+
+  pub fn scale(&self, a: f32) -> Vector3 {
+    Vector3 {
+      x: self.x * a,
+      y: self.y * a,
+      z: self.z * a,
+    }
+  }
+
+  // component-wise product, used for colors
+  pub fn mul(&self, v: &Vector3) -> Vector3 {
+    Vector3 {
+      x: self.x * v.x,
+      y: self.y * v.y,
+      z: self.z * v.z,
+    }
+  }
+
+  // same weighting as VertexUV::lerp, a = 1.0 gives self
+  pub fn lerp(&self, b: &Vector3, a: f32) -> Vector3 {
+    Vector3 {
+      x: self.x * a + b.x * (1.0 - a),
+      y: self.y * a + b.y * (1.0 - a),
+      z: self.z * a + b.z * (1.0 - a),
+    }
+  }
+
   pub fn dot(&self, v: &Vector3) -> f32 {
     return self.x*v.x + self.y*v.y + self.z*v.z;
   }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vector4 {
   pub x: f32,
   pub y: f32,
@@ -177,18 +211,32 @@ impl Vector4 {
   pub fn cross(&self, v: &Self) -> Self {
     Self {
       x: self.y * v.z - self.z * v.y,
-      y: self.x * v.z - self.z * v.x,
+      y: self.z * v.x - self.x * v.z,
       z: self.x * v.y - self.y * v.x,
       w: 1.0,
     }
   }
 
+  // normalizes only the direction part, w is left untouched
+  pub fn normal_xyz(&self) -> Self {
+    let len = (self.x*self.x + self.y*self.y + self.z*self.z).sqrt();
+    if len < 1e-8 {
+      return Self { x: self.x, y: self.y, z: self.z, w: self.w };
+    }
+    Self {
+      x: self.x / len,
+      y: self.y / len,
+      z: self.z / len,
+      w: self.w,
+    }
+  }
+
   pub fn eq(&self, other: &Self) -> bool {
     self.x == other.x && self.y == other.y && self.z == other.z && self.w == other.w
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Matrix4 {
   pub m: [[f32; 4]; 4],
 }
@@ -233,7 +281,7 @@ impl Matrix4 {
       m: [
         [angle.cos(), 0.0, angle.sin(), 0.0],
         [0.0, 1.0, 0.0, 0.0],
-        [-angle.sin(), 0.0, -angle.cos(), 0.0],
+        [-angle.sin(), 0.0, angle.cos(), 0.0],
         [0.0, 0.0, 0.0, 1.0],
       ]
     }
@@ -250,6 +298,58 @@ impl Matrix4 {
     }
   }
 
+  pub fn scaling(x: f32, y: f32, z: f32) -> Matrix4 {
+    Matrix4 {
+      m: [
+        [x, 0.0, 0.0, 0.0],
+        [0.0, y, 0.0, 0.0],
+        [0.0, 0.0, z, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+      ]
+    }
+  }
+
+  // rotation from a unit quaternion, for row vectors
+  pub fn from_quaternion(x: f32, y: f32, z: f32, w: f32) -> Matrix4 {
+    Matrix4 {
+      m: [
+        [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y + z * w), 2.0 * (x * z - y * w), 0.0],
+        [2.0 * (x * y - z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z + x * w), 0.0],
+        [2.0 * (x * z + y * w), 2.0 * (y * z - x * w), 1.0 - 2.0 * (x * x + y * y), 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+      ]
+    }
+  }
+
+  // inverse transpose of the upper 3x3, keeps normals perpendicular under non-uniform scale
+  pub fn normal_matrix(&self) -> Matrix4 {
+    let m = &self.m;
+    let c00 = m[1][1] * m[2][2] - m[1][2] * m[2][1];
+    let c01 = m[1][2] * m[2][0] - m[1][0] * m[2][2];
+    let c02 = m[1][0] * m[2][1] - m[1][1] * m[2][0];
+    let c10 = m[0][2] * m[2][1] - m[0][1] * m[2][2];
+    let c11 = m[0][0] * m[2][2] - m[0][2] * m[2][0];
+    let c12 = m[0][1] * m[2][0] - m[0][0] * m[2][1];
+    let c20 = m[0][1] * m[1][2] - m[0][2] * m[1][1];
+    let c21 = m[0][2] * m[1][0] - m[0][0] * m[1][2];
+    let c22 = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+
+    let det = m[0][0] * c00 + m[0][1] * c01 + m[0][2] * c02;
+    if det.abs() < 1e-12 {
+      return Matrix4::identity();
+    }
+    let inv_det = 1.0 / det;
+
+    Matrix4 {
+      m: [
+        [c00 * inv_det, c01 * inv_det, c02 * inv_det, 0.0],
+        [c10 * inv_det, c11 * inv_det, c12 * inv_det, 0.0],
+        [c20 * inv_det, c21 * inv_det, c22 * inv_det, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+      ]
+    }
+  }
+
   pub fn projection(fov: f32, aspect: f32, near: f32, far: f32) -> Matrix4 {
     let f = 1.0 / (fov / 2.0).tan();
     let inv_r = 1.0 / (near - far);
@@ -264,37 +364,67 @@ impl Matrix4 {
     }
   }
 
-  pub fn lookat_rot(eye: &Vector3, target: &Vector3) -> Matrix4 {
-    let mut mat = Matrix4::identity();
-    let forward = target.sub(eye);
-    let focus = forward.len();
+  // perspective through the window left..right, bottom..top on the near plane,
+  // which doesn't have to be centered on the view axis
+  pub fn frustum(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Matrix4 {
+    let w = right - left;
+    let h = top - bottom;
 
-    let ax = -(forward.x.atan2(forward.z));
-    let ay = (forward.y / focus).asin();
-    let az = 0.0 as f32;
+    Matrix4 {
+      m: [
+        [2.0 * near / w, 0.0, 0.0, 0.0],
+        [0.0, -2.0 * near / h, 0.0, 0.0],
+        [-(right + left) / w, (top + bottom) / h, far / (far - near), 1.0],
+        [0.0, 0.0, near * far / (near - far), 0.0],
+      ]
+    }
+  }
 
-    let sinx = ax.sin();
-    let cosx = ax.cos();
-    let siny = ay.sin();
-    let cosy = ay.cos();
-    let sinz = az.sin();
-    let cosz = az.cos();
+  // parallel projection of the box left..right, bottom..top, near..far in view
+  // space. clip z runs 0..1 and w stays 1 like the perspective ones
+  pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Matrix4 {
+    let w = right - left;
+    let h = top - bottom;
 
-    mat.m[0][0] =  sinx * siny * sinz + cosx * cosz;
-    mat.m[1][0] =  cosy * sinz;
-    mat.m[2][0] =  sinx * cosz - cosx * siny * sinz;
-    mat.m[0][1] =  sinx * siny * cosz - cosx * sinz;
-    mat.m[1][1] =  cosy * cosz;
-    mat.m[2][1] = -cosx * siny * cosz - sinx * sinz;
-    mat.m[0][2] = -sinx * cosy;
-    mat.m[1][2] =  siny;
-    mat.m[2][2] =  cosx * cosy;
+    Matrix4 {
+      m: [
+        [2.0 / w, 0.0, 0.0, 0.0],
+        [0.0, -2.0 / h, 0.0, 0.0],
+        [0.0, 0.0, 1.0 / (far - near), 0.0],
+        [-(right + left) / w, (top + bottom) / h, -near / (far - near), 1.0],
+      ]
+    }
+  }
 
-    return mat;
+  // world to view rotation, its columns are the camera's right, up and forward
+  // axes in world space. up only has to be roughly up, when it lines up with
+  // the view direction any perpendicular is used instead of flipping
+  pub fn lookat_rot(eye: &Vector3, target: &Vector3, up: &Vector3) -> Matrix4 {
+    let offset = target.sub(eye);
+    if offset.len() < 1e-8 {
+      return Matrix4::identity();
+    }
+    let forward = offset.normal();
+    let mut right = up.cross(&forward);
+    if right.len() < 1e-6 {
+      let other = if forward.x.abs() < 0.9 { Vector3 { x: 1.0, y: 0.0, z: 0.0 } } else { Vector3 { x: 0.0, y: 0.0, z: 1.0 } };
+      right = other.cross(&forward);
+    }
+    let right = right.normal();
+    let up = forward.cross(&right);
+
+    Matrix4 {
+      m: [
+        [right.x, up.x, forward.x, 0.0],
+        [right.y, up.y, forward.y, 0.0],
+        [right.z, up.z, forward.z, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+      ]
+    }
   }
 
-  pub fn lookat(eye: &Vector3, target: &Vector3) -> Matrix4 {
-    let mut mat = Matrix4::lookat_rot(eye, target);
+  pub fn lookat(eye: &Vector3, target: &Vector3, up: &Vector3) -> Matrix4 {
+    let mut mat = Matrix4::lookat_rot(eye, target, up);
     let pivot = eye.negate();
 
     mat.m[3][0] = mat.m[0][0] * pivot.x + mat.m[1][0] * pivot.y + mat.m[2][0] * pivot.z + mat.m[3][0];
@@ -347,6 +477,220 @@ impl Matrix4 {
   }
 }
 
+// rotation as a unit quaternion, composed like matrices on column vectors:
+// a.multiply(&b) rotates by b first and then by a
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+  pub x: f32,
+  pub y: f32,
+  pub z: f32,
+  pub w: f32,
+}
+
+impl Quaternion {
+  pub fn identity() -> Quaternion {
+    Quaternion { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }
+  }
+
+  // angle in radians, around +z a positive angle turns x towards y
+  pub fn from_axis_angle(axis: &Vector3, angle: f32) -> Quaternion {
+    let axis = axis.normal();
+    let (sin, cos) = (angle / 2.0).sin_cos();
+    Quaternion { x: axis.x * sin, y: axis.y * sin, z: axis.z * sin, w: cos }
+  }
+
+  // inverse of Matrix4::from_quaternion for a pure rotation matrix
+  pub fn from_matrix(m: &Matrix4) -> Quaternion {
+    let m = &m.m;
+    let trace = m[0][0] + m[1][1] + m[2][2];
+    let q = if trace > 0.0 {
+      let s = (trace + 1.0).sqrt() * 2.0;
+      Quaternion { x: (m[1][2] - m[2][1]) / s, y: (m[2][0] - m[0][2]) / s, z: (m[0][1] - m[1][0]) / s, w: s / 4.0 }
+    } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+      let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+      Quaternion { x: s / 4.0, y: (m[0][1] + m[1][0]) / s, z: (m[0][2] + m[2][0]) / s, w: (m[1][2] - m[2][1]) / s }
+    } else if m[1][1] > m[2][2] {
+      let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+      Quaternion { x: (m[0][1] + m[1][0]) / s, y: s / 4.0, z: (m[1][2] + m[2][1]) / s, w: (m[2][0] - m[0][2]) / s }
+    } else {
+      let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+      Quaternion { x: (m[0][2] + m[2][0]) / s, y: (m[1][2] + m[2][1]) / s, z: s / 4.0, w: (m[0][1] - m[1][0]) / s }
+    };
+    q.normal()
+  }
+
+  pub fn multiply(&self, b: &Quaternion) -> Quaternion {
+    let a = self;
+    Quaternion {
+      x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+      y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+      z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+      w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+    }
+  }
+
+  // the opposite rotation
+  pub fn conjugate(&self) -> Quaternion {
+    Quaternion { x: -self.x, y: -self.y, z: -self.z, w: self.w }
+  }
+
+  // falls back to no rotation for a zero quaternion
+  pub fn normal(&self) -> Quaternion {
+    let len = (self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w).sqrt();
+    if len < 1e-8 {
+      return Quaternion::identity();
+    }
+    Quaternion { x: self.x / len, y: self.y / len, z: self.z / len, w: self.w / len }
+  }
+
+  pub fn matrix(&self) -> Matrix4 {
+    Matrix4::from_quaternion(self.x, self.y, self.z, self.w)
+  }
+
+  pub fn rotate(&self, v: &Vector3) -> Vector3 {
+    let r = Vector4 { x: v.x, y: v.y, z: v.z, w: 0.0 }.multiply(&self.matrix());
+    Vector3 { x: r.x, y: r.y, z: r.z }
+  }
+}
+
+// axis aligned box, min > max when empty
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+  pub min: Vector3,
+  pub max: Vector3,
+}
+
+impl Aabb {
+  pub fn empty() -> Aabb {
+    Aabb {
+      min: Vector3 { x: f32::MAX, y: f32::MAX, z: f32::MAX },
+      max: Vector3 { x: f32::MIN, y: f32::MIN, z: f32::MIN },
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.min.x > self.max.x
+  }
+
+  pub fn extend(&mut self, p: &Vector3) {
+    self.min = Vector3 { x: self.min.x.min(p.x), y: self.min.y.min(p.y), z: self.min.z.min(p.z) };
+    self.max = Vector3 { x: self.max.x.max(p.x), y: self.max.y.max(p.y), z: self.max.z.max(p.z) };
+  }
+
+  pub fn center(&self) -> Vector3 {
+    self.min.add(&self.max).scale(0.5)
+  }
+
+  pub fn extents(&self) -> Vector3 {
+    self.max.sub(&self.min).scale(0.5)
+  }
+
+  // box around the transformed box, from the absolute values of the rotation part
+  pub fn transform(&self, m: &Matrix4) -> Aabb {
+    if self.is_empty() {
+      return *self;
+    }
+    let c = Vector4 { x: self.center().x, y: self.center().y, z: self.center().z, w: 1.0 }.multiply(m);
+    let e = self.extents();
+    let axis = |col: usize| e.x * m.m[0][col].abs() + e.y * m.m[1][col].abs() + e.z * m.m[2][col].abs();
+    let half = Vector3 { x: axis(0), y: axis(1), z: axis(2) };
+    let center = Vector3 { x: c.x, y: c.y, z: c.z };
+    Aabb { min: center.sub(&half), max: center.add(&half) }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+  pub center: Vector3,
+  pub radius: f32,
+}
+
+impl Sphere {
+  // centered on the box, so not the smallest sphere but cheap and stable
+  pub fn around(points: &[Vector3], bounds: &Aabb) -> Sphere {
+    let center = if bounds.is_empty() { Vector3::new() } else { bounds.center() };
+    let radius = points.iter().map(|p| p.sub(&center).len()).fold(0.0, f32::max);
+    Sphere { center, radius }
+  }
+
+  // the radius grows by the largest axis scale
+  pub fn transform(&self, m: &Matrix4) -> Sphere {
+    let c = Vector4 { x: self.center.x, y: self.center.y, z: self.center.z, w: 1.0 }.multiply(m);
+    let row = |r: usize| (m.m[r][0] * m.m[r][0] + m.m[r][1] * m.m[r][1] + m.m[r][2] * m.m[r][2]).sqrt();
+    let scale = row(0).max(row(1)).max(row(2));
+    Sphere { center: Vector3 { x: c.x, y: c.y, z: c.z }, radius: self.radius * scale }
+  }
+}
+
+// points with normal . p + d >= 0 are inside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+  pub normal: Vector3,
+  pub d: f32,
+}
+
+impl Plane {
+  fn normalized(x: f32, y: f32, z: f32, d: f32) -> Plane {
+    let len = (x * x + y * y + z * z).sqrt();
+    if len < 1e-12 {
+      return Plane { normal: Vector3 { x, y, z }, d };
+    }
+    Plane { normal: Vector3 { x: x / len, y: y / len, z: z / len }, d: d / len }
+  }
+
+  pub fn distance(&self, p: &Vector3) -> f32 {
+    self.normal.dot(p) + self.d
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+  // left, right, bottom, top, near, far
+  pub planes: [Plane; 6],
+}
+
+impl Frustum {
+  // planes of a view projection matrix, in the space the matrix is applied to.
+  // clip space is -w..w in x and y and 0..w in z, each column gives one clip coordinate
+  pub fn from_matrix(m: &Matrix4) -> Frustum {
+    let col = |c: usize| [m.m[0][c], m.m[1][c], m.m[2][c], m.m[3][c]];
+    let (x, y, z, w) = (col(0), col(1), col(2), col(3));
+    let plane = |a: [f32; 4], sign: f32, b: [f32; 4]| {
+      Plane::normalized(a[0] + sign * b[0], a[1] + sign * b[1], a[2] + sign * b[2], a[3] + sign * b[3])
+    };
+    Frustum {
+      planes: [
+        plane(w, 1.0, x),
+        plane(w, -1.0, x),
+        plane(w, 1.0, y),
+        plane(w, -1.0, y),
+        plane(z, 0.0, z),
+        plane(w, -1.0, z),
+      ]
+    }
+  }
+
+  // false only when the sphere is entirely outside one plane
+  pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+    self.planes.iter().all(|p| p.distance(&sphere.center) >= -sphere.radius)
+  }
+
+  // tests the corner furthest along each plane normal
+  pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+    if aabb.is_empty() {
+      return false;
+    }
+    self.planes.iter().all(|p| {
+      let corner = Vector3 {
+        x: if p.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+        y: if p.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+        z: if p.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+      };
+      p.distance(&corner) >= 0.0
+    })
+  }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -387,4 +731,148 @@ mod tests {
 
         assert_eq!(transformed, vertex);       
     }
+
+    #[test]
+    fn test_vector3_cross() {
+        let x = Vector3 { x: 1.0, y: 0.0, z: 0.0 };
+        let y = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+        let z = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+        assert_eq!(x.cross(&y), z);
+        assert_eq!(y.cross(&z), x);
+        assert_eq!(z.cross(&x), y);
+    }
+
+    #[test]
+    fn test_matrix4_scaling() {
+        let vertex = Vector4 { x: 1.0, y: 2.0, z: 3.0, w: 1.0 };
+        let transformed = vertex.multiply(&Matrix4::scaling(2.0, 3.0, 4.0));
+        assert_eq!(transformed, Vector4 { x: 2.0, y: 6.0, z: 12.0, w: 1.0 });
+    }
+
+    #[test]
+    fn test_aabb_transform() {
+        let mut aabb = Aabb::empty();
+        assert!(aabb.is_empty());
+        aabb.extend(&Vector3 { x: -1.0, y: -2.0, z: 0.0 });
+        aabb.extend(&Vector3 { x: 1.0, y: 2.0, z: 0.0 });
+
+        // a quarter turn around z swaps the x and y extents
+        let m = Matrix4::rotation_z(std::f32::consts::FRAC_PI_2).multiply(&Matrix4::identity().translate_xyz(10.0, 0.0, 0.0));
+        let moved = aabb.transform(&m);
+        assert!((moved.min.x - 8.0).abs() < 1e-5 && (moved.max.x - 12.0).abs() < 1e-5);
+        assert!((moved.min.y + 1.0).abs() < 1e-5 && (moved.max.y - 1.0).abs() < 1e-5);
+
+        let sphere = Sphere::around(&[aabb.min, aabb.max], &aabb).transform(&Matrix4::scaling(1.0, 3.0, 1.0));
+        assert!((sphere.radius - 3.0 * 5.0f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_frustum_culling() {
+        // camera at the origin looking down +z
+        let frustum = Frustum::from_matrix(&Matrix4::projection(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 100.0));
+        let sphere = |x: f32, z: f32, radius: f32| frustum.intersects_sphere(&Sphere { center: Vector3 { x, y: 0.0, z }, radius });
+        assert!(sphere(0.0, 10.0, 1.0));
+        assert!(!sphere(0.0, -5.0, 1.0));
+        assert!(!sphere(0.0, 200.0, 1.0));
+        // 90 degrees wide, x = z is the right plane
+        assert!(!sphere(13.0, 10.0, 2.0));
+        assert!(sphere(11.0, 10.0, 2.0));
+
+        let aabb = |min: Vector3, max: Vector3| frustum.intersects_aabb(&Aabb { min, max });
+        assert!(aabb(Vector3 { x: -1.0, y: -1.0, z: 0.0 }, Vector3 { x: 1.0, y: 1.0, z: 2.0 }));
+        assert!(!aabb(Vector3 { x: -1.0, y: -1.0, z: -3.0 }, Vector3 { x: 1.0, y: 1.0, z: 0.5 }));
+        assert!(!frustum.intersects_aabb(&Aabb::empty()));
+    }
+
+    #[test]
+    fn test_matrix4_frustum_orthographic() {
+        let ndc = |v: Vector4, m: &Matrix4| {
+          let c = v.multiply(m);
+          (c.x / c.w, c.y / c.w, c.z / c.w)
+        };
+        let close = |a: (f32, f32, f32), b: (f32, f32, f32)| (a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5 && (a.2 - b.2).abs() < 1e-5;
+
+        // a centered window matches the symmetric projection
+        let tan = (0.5f32).tan();
+        let symmetric = Matrix4::projection(1.0, 2.0, 1.0, 50.0);
+        let centered = Matrix4::frustum(-2.0 * tan, 2.0 * tan, -tan, tan, 1.0, 50.0);
+        let p = Vector4 { x: 0.3, y: -0.2, z: 4.0, w: 1.0 };
+        assert!(close(ndc(p, &symmetric), ndc(p, &centered)));
+
+        // off axis, the window corners land on the screen corners, y points down
+        let off_axis = Matrix4::frustum(0.0, 1.0, 0.0, 0.5, 2.0, 10.0);
+        assert!(close(ndc(Vector4 { x: 0.0, y: 0.0, z: 2.0, w: 1.0 }, &off_axis), (-1.0, 1.0, 0.0)));
+        assert!(close(ndc(Vector4 { x: 5.0, y: 2.5, z: 10.0, w: 1.0 }, &off_axis), (1.0, -1.0, 1.0)));
+
+        // orthographic keeps w at 1 and ignores distance for x and y
+        let ortho = Matrix4::orthographic(-4.0, 4.0, -3.0, 3.0, 1.0, 11.0);
+        let near = Vector4 { x: 4.0, y: 3.0, z: 1.0, w: 1.0 }.multiply(&ortho);
+        let far = Vector4 { x: 4.0, y: 3.0, z: 11.0, w: 1.0 }.multiply(&ortho);
+        assert!(close((near.x, near.y, near.z), (1.0, -1.0, 0.0)) && near.w == 1.0);
+        assert!(close((far.x, far.y, far.z), (1.0, -1.0, 1.0)) && far.w == 1.0);
+    }
+
+    #[test]
+    fn test_matrix4_lookat_up() {
+        let eye = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+        let axes = |m: &Matrix4| [0, 1, 2].map(|c| Vector3 { x: m.m[0][c], y: m.m[1][c], z: m.m[2][c] });
+
+        // banked with +x as up, right points down
+        let [right, up, forward] = axes(&Matrix4::lookat_rot(&eye, &Vector3 { x: 0.0, y: 0.0, z: 5.0 }, &Vector3 { x: 1.0, y: 0.0, z: 0.0 }));
+        assert_eq!((right, up, forward), (Vector3 { x: 0.0, y: -1.0, z: 0.0 }, Vector3 { x: 1.0, y: 0.0, z: 0.0 }, Vector3 { x: 0.0, y: 0.0, z: 1.0 }));
+
+        // straight up along the up vector still gives an orthonormal frame
+        let [right, up, forward] = axes(&Matrix4::lookat_rot(&eye, &Vector3 { x: 0.0, y: 3.0, z: 0.0 }, &Vector3 { x: 0.0, y: 1.0, z: 0.0 }));
+        assert_eq!(forward, Vector3 { x: 0.0, y: 1.0, z: 0.0 });
+        assert!(right.dot(&forward).abs() < 1e-6 && up.dot(&forward).abs() < 1e-6 && (right.len() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_quaternion() {
+        let close = |a: Vector3, b: Vector3| a.sub(&b).len() < 1e-5;
+        let x = Vector3 { x: 1.0, y: 0.0, z: 0.0 };
+        let quarter_z = Quaternion::from_axis_angle(&Vector3 { x: 0.0, y: 0.0, z: 2.0 }, std::f32::consts::FRAC_PI_2);
+        let quarter_y = Quaternion::from_axis_angle(&Vector3 { x: 0.0, y: 1.0, z: 0.0 }, std::f32::consts::FRAC_PI_2);
+        assert!(close(quarter_z.rotate(&x), Vector3 { x: 0.0, y: 1.0, z: 0.0 }));
+        // z first, then y
+        assert!(close(quarter_y.multiply(&quarter_z).rotate(&x), Vector3 { x: 0.0, y: 1.0, z: 0.0 }));
+        assert!(close(quarter_z.multiply(&quarter_y).rotate(&x), Vector3 { x: 0.0, y: 0.0, z: -1.0 }));
+        assert!(close(quarter_z.conjugate().rotate(&quarter_z.rotate(&x)), x));
+
+        // back from the matrix, through every branch of from_matrix
+        for &(axis, angle) in [(x, 0.3), (x, 3.0), (Vector3 { x: 0.0, y: 1.0, z: 0.0 }, 3.0), (Vector3 { x: 1.0, y: 2.0, z: -3.0 }, 2.9)].iter() {
+            let q = Quaternion::from_axis_angle(&axis, angle);
+            let back = Quaternion::from_matrix(&q.matrix());
+            let v = Vector3 { x: 0.2, y: -0.7, z: 0.4 };
+            assert!(close(back.rotate(&v), q.rotate(&v)));
+        }
+    }
+
+    #[test]
+    fn test_matrix4_from_quaternion() {
+        // quarter turn around z takes x to y
+        let half = std::f32::consts::FRAC_PI_4;
+        let rotation = Matrix4::from_quaternion(0.0, 0.0, half.sin(), half.cos());
+        let v = Vector4 { x: 1.0, y: 0.0, z: 0.0, w: 1.0 }.multiply(&rotation);
+        assert!(v.x.abs() < 1e-6);
+        assert!((v.y - 1.0).abs() < 1e-6);
+        assert!(v.z.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_matrix4_normal_matrix() {
+        // normal of the plane x = y stays perpendicular to it after stretching x
+        let scale = Matrix4::scaling(2.0, 1.0, 1.0);
+        let normal = Vector4 { x: 1.0, y: -1.0, z: 0.0, w: 0.0 }.multiply(&scale.normal_matrix());
+        let tangent = Vector4 { x: 1.0, y: 1.0, z: 0.0, w: 0.0 }.multiply(&scale);
+        assert_eq!(normal.dot(&tangent), 0.0);
+
+        let rotation = Matrix4::rotation_z(0.5);
+        let rotated = rotation.normal_matrix();
+        for row in 0..3 {
+          for col in 0..3 {
+            assert!((rotated.m[row][col] - rotation.m[row][col]).abs() < 1e-6);
+          }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+use crate::math;
+use super::VertexUV;
+
+// a half space polygons are clipped against, the side with a
+// positive distance is kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipPlane {
+    // dot product with the clip space x, y, z, w
+    Clip(math::Vector4),
+    // against the world space position, for user clip planes
+    World(math::Plane),
+}
+
+impl ClipPlane {
+    pub fn distance(&self, v: &VertexUV) -> f32 {
+        match self {
+            ClipPlane::Clip(p) => p.x * v.x + p.y * v.y + p.z * v.z + p.w * v.w,
+            ClipPlane::World(p) => p.distance(&v.position),
+        }
+    }
+}
+
+// the view volume of Matrix4::projection, -w..w in x and y and 0..w in z
+pub const FRUSTUM_PLANES: [ClipPlane; 6] = [
+    // near, far
+    ClipPlane::Clip(math::Vector4 { x: 0.0, y: 0.0, z: 1.0, w: 0.0 }),
+    ClipPlane::Clip(math::Vector4 { x: 0.0, y: 0.0, z: -1.0, w: 1.0 }),
+    // left, right
+    ClipPlane::Clip(math::Vector4 { x: 1.0, y: 0.0, z: 0.0, w: 1.0 }),
+    ClipPlane::Clip(math::Vector4 { x: -1.0, y: 0.0, z: 0.0, w: 1.0 }),
+    // bottom, top
+    ClipPlane::Clip(math::Vector4 { x: 0.0, y: 1.0, z: 0.0, w: 1.0 }),
+    ClipPlane::Clip(math::Vector4 { x: 0.0, y: -1.0, z: 0.0, w: 1.0 }),
+];
+
+// sutherland-hodgman against each plane in turn, in place. every attribute is
+// interpolated through VertexUV::lerp. fewer than three vertices left means
+// nothing to draw and the polygon is emptied. output is scratch space kept by
+// the caller so clipping doesn't allocate once both have grown to size
+pub fn clip_polygon_into(polygon: &mut Vec<VertexUV>, output: &mut Vec<VertexUV>, planes: &[ClipPlane]) {
+    for plane in planes.iter() {
+        if polygon.len() < 3 {
+            break;
+        }
+        output.clear();
+
+        let mut previous = &polygon[polygon.len() - 1];
+        let mut previous_distance = plane.distance(previous);
+        for current in polygon.iter() {
+            let distance = plane.distance(current);
+            // vertices exactly on the plane count as inside and aren't split
+            if (distance > 0.0 && previous_distance < 0.0) || (distance < 0.0 && previous_distance > 0.0) {
+                // previous + t * (current - previous) lands on the plane
+                let t = previous_distance / (previous_distance - distance);
+                output.push(current.lerp(previous, t));
+            }
+            if distance >= 0.0 {
+                output.push(current.clone());
+            }
+            previous = current;
+            previous_distance = distance;
+        }
+        // swaps the vectors, not their contents
+        std::mem::swap(polygon, output);
+    }
+
+    if polygon.len() < 3 {
+        polygon.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32, w: f32) -> VertexUV {
+        let zero = math::Vector3::new();
+        VertexUV { x, y, z, w, u: x, v: y, diffuse: zero, specular: zero, color: zero,
+            position: math::Vector3 { x, y, z }, normal: zero, mesh_u: 0.0, mesh_v: 0.0, tangent: zero, bitangent: zero }
+    }
+
+    fn clip_polygon(vertices: Vec<VertexUV>, planes: &[ClipPlane]) -> Vec<VertexUV> {
+        let mut polygon = vertices;
+        clip_polygon_into(&mut polygon, &mut vec![], planes);
+        polygon
+    }
+
+    fn inside(v: &VertexUV) -> bool {
+        FRUSTUM_PLANES.iter().all(|p| p.distance(v) >= -1e-5)
+    }
+
+    #[test]
+    fn test_clip_inside_untouched() {
+        let triangle = vec![vertex(0.0, 0.0, 0.5, 1.0), vertex(0.5, 0.0, 0.5, 1.0), vertex(0.0, 0.5, 0.5, 1.0)];
+        let clipped = clip_polygon(triangle.clone(), &FRUSTUM_PLANES);
+        assert_eq!(clipped.len(), 3);
+        assert_eq!((clipped[0].x, clipped[1].x, clipped[2].y), (0.0, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_clip_every_plane() {
+        // one corner pokes through each plane in turn, the triangle becomes a quad
+        let pokes = [
+            vertex(0.0, 0.0, -1.0, 1.0),
+            vertex(0.0, 0.0, 2.0, 1.0),
+            vertex(-3.0, 0.0, 0.5, 1.0),
+            vertex(3.0, 0.0, 0.5, 1.0),
+            vertex(0.0, -3.0, 0.5, 1.0),
+            vertex(0.0, 3.0, 0.5, 1.0),
+        ];
+        for (i, poke) in pokes.iter().enumerate() {
+            let base = if i < 2 {
+                [vertex(-0.5, -0.5, 0.5, 1.0), vertex(0.5, -0.5, 0.5, 1.0)]
+            } else if i < 4 {
+                [vertex(0.0, -0.5, 0.5, 1.0), vertex(0.0, 0.5, 0.5, 1.0)]
+            } else {
+                [vertex(-0.5, 0.0, 0.5, 1.0), vertex(0.5, 0.0, 0.5, 1.0)]
+            };
+            let clipped = clip_polygon(vec![base[0].clone(), base[1].clone(), poke.clone()], &FRUSTUM_PLANES[i..i + 1]);
+            assert_eq!(clipped.len(), 4, "plane {}", i);
+            assert!(clipped.iter().all(inside), "plane {}", i);
+        }
+    }
+
+    #[test]
+    fn test_clip_interpolates_attributes() {
+        let mut a = vertex(0.0, 0.0, 0.5, 1.0);
+        let mut b = vertex(2.0, 0.0, 0.5, 1.0);
+        a.color = math::Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+        b.color = math::Vector3 { x: 1.0, y: 0.0, z: 0.0 };
+        a.mesh_u = 0.0;
+        b.mesh_u = 4.0;
+        let c = vertex(0.0, 0.5, 0.5, 1.0);
+
+        // x = w cuts the a-b edge halfway
+        let clipped = clip_polygon(vec![a, b, c], &FRUSTUM_PLANES[3..4]);
+        let cut = clipped.iter().find(|v| (v.x - 1.0).abs() < 1e-6 && v.y == 0.0).unwrap();
+        assert_eq!((cut.color.x, cut.mesh_u, cut.u), (0.5, 2.0, 1.0));
+    }
+
+    #[test]
+    fn test_clip_corner_cases() {
+        // fully outside
+        let outside = vec![vertex(2.0, 0.0, 0.5, 1.0), vertex(3.0, 0.0, 0.5, 1.0), vertex(2.0, 1.0, 0.5, 1.0)];
+        assert!(clip_polygon(outside, &FRUSTUM_PLANES).is_empty());
+
+        // touching a plane with one vertex only leaves a point, which is dropped
+        let touching = vec![vertex(1.0, 0.0, 0.5, 1.0), vertex(2.0, -0.5, 0.5, 1.0), vertex(2.0, 0.5, 0.5, 1.0)];
+        assert!(clip_polygon(touching, &FRUSTUM_PLANES).is_empty());
+
+        // covering a corner of the screen cuts through two planes at once
+        let corner = vec![vertex(0.0, 0.0, 0.5, 1.0), vertex(1.5, 0.0, 0.5, 1.0), vertex(0.0, 1.5, 0.5, 1.0)];
+        let clipped = clip_polygon(corner, &FRUSTUM_PLANES);
+        assert_eq!(clipped.len(), 5);
+        assert!(clipped.iter().all(inside));
+
+        // bigger than the whole view in every direction
+        let huge = vec![vertex(-10.0, -10.0, 0.5, 1.0), vertex(30.0, -10.0, 0.5, 1.0), vertex(-10.0, 30.0, 0.5, 1.0)];
+        let clipped = clip_polygon(huge, &FRUSTUM_PLANES);
+        assert_eq!(clipped.len(), 4);
+        assert!(clipped.iter().all(inside));
+
+        // crossing the camera plane, w goes negative. z = w - 1 like a projection with the near plane at 1
+        let behind = vec![vertex(0.0, 0.0, 1.0, 2.0), vertex(0.0, 0.0, -2.0, -1.0), vertex(0.2, 0.2, 1.0, 2.0)];
+        let clipped = clip_polygon(behind, &FRUSTUM_PLANES);
+        assert_eq!(clipped.len(), 4);
+        assert!(clipped.iter().all(|v| v.w > 0.0 && inside(v)));
+
+        assert!(clip_polygon(vec![], &FRUSTUM_PLANES).is_empty());
+    }
+
+    #[test]
+    fn test_clip_user_plane() {
+        // keep x <= 0.25 in world space
+        let plane = ClipPlane::World(math::Plane { normal: math::Vector3 { x: -1.0, y: 0.0, z: 0.0 }, d: 0.25 });
+        let triangle = vec![vertex(0.0, 0.0, 0.5, 1.0), vertex(0.5, 0.0, 0.5, 1.0), vertex(0.0, 0.5, 0.5, 1.0)];
+        let clipped = clip_polygon(triangle, &[plane]);
+        assert_eq!(clipped.len(), 4);
+        assert!(clipped.iter().all(|v| v.position.x <= 0.25 + 1e-6));
+    }
+}
@@ -0,0 +1,450 @@
+use std::f32::consts::FRAC_PI_2;
+use crate::math;
+use super::{CameraControl, ProjectionMode};
+use super::scene::Camera;
+
+// first person pitch stops just short of straight up and down
+const LOOK_LIMIT: f32 = FRAC_PI_2 * 0.99;
+// radians per second for keys that turn rather than move
+const KEY_TURN_SPEED: f32 = 1.5;
+
+// movement keys held down, by KeyboardEvent.code
+#[derive(Debug, Default, Clone, Copy)]
+struct Keys {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+    roll_left: bool,
+    roll_right: bool,
+    shift: bool,
+}
+
+impl Keys {
+    fn get_mut(&mut self, code: &str) -> Option<&mut bool> {
+        match code {
+            "KeyW" | "ArrowUp" => Some(&mut self.forward),
+            "KeyS" | "ArrowDown" => Some(&mut self.back),
+            "KeyA" | "ArrowLeft" => Some(&mut self.left),
+            "KeyD" | "ArrowRight" => Some(&mut self.right),
+            "Space" => Some(&mut self.up),
+            "KeyC" | "ControlLeft" => Some(&mut self.down),
+            "KeyQ" => Some(&mut self.roll_left),
+            "KeyE" => Some(&mut self.roll_right),
+            "ShiftLeft" | "ShiftRight" => Some(&mut self.shift),
+            _ => None,
+        }
+    }
+
+    // right, up, forward and roll each in -1..1
+    fn axes(&self) -> (f32, f32, f32, f32) {
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        (
+            axis(self.right, self.left),
+            axis(self.up, self.down),
+            axis(self.forward, self.back),
+            axis(self.roll_left, self.roll_right),
+        )
+    }
+}
+
+// turns input events into camera motion. events only queue up motion, update
+// plays it out over the damping time so the camera eases in and coasts to a stop
+#[derive(Debug)]
+pub struct CameraControls {
+    pub mode: CameraControl,
+    // seconds for queued motion to mostly play out, 0 applies it at once
+    pub damping: f32,
+    // radians per pixel dragged
+    pub look_speed: f32,
+    // world units per second for first person and fly
+    pub move_speed: f32,
+    // orbit limits, pitch in radians above the target's horizon
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    // orbit center
+    pub target: math::Vector3,
+    distance: f32,
+    // view direction for orbit and first person, yaw 0 looks down +z
+    yaw: f32,
+    pitch: f32,
+    // queued motion: radians, log of the zoom factor, pixels and world units
+    pending_yaw: f32,
+    pending_pitch: f32,
+    pending_roll: f32,
+    pending_zoom: f32,
+    pending_pan: (f32, f32),
+    pending_dolly: f32,
+    // world units per second, eases towards what the keys ask for
+    velocity: math::Vector3,
+    // button and last position of the dragging pointer
+    pointer: Option<(u32, f32, f32)>,
+    // identifier and last position of up to two touches
+    touches: [Option<(i32, f32, f32)>; 2],
+    keys: Keys,
+}
+
+impl CameraControls {
+    pub fn new() -> CameraControls {
+        CameraControls {
+            mode: CameraControl::None,
+            damping: 0.1,
+            look_speed: 0.005,
+            move_speed: 2.0,
+            min_distance: 0.01,
+            max_distance: f32::INFINITY,
+            min_pitch: -LOOK_LIMIT,
+            max_pitch: LOOK_LIMIT,
+            target: math::Vector3::new(),
+            distance: 1.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            pending_yaw: 0.0,
+            pending_pitch: 0.0,
+            pending_roll: 0.0,
+            pending_zoom: 0.0,
+            pending_pan: (0.0, 0.0),
+            pending_dolly: 0.0,
+            velocity: math::Vector3::new(),
+            pointer: None,
+            touches: [None; 2],
+            keys: Keys::default(),
+        }
+    }
+
+    // picks up a camera moved from outside, target is what it's aimed at if anything.
+    // queued motion is dropped so it doesn't fight the new pose
+    pub fn sync(&mut self, camera: &Camera, target: Option<math::Vector3>) {
+        let forward = camera.axes().2;
+        self.yaw = forward.x.atan2(forward.z);
+        self.pitch = forward.y.clamp(-1.0, 1.0).asin();
+        match target {
+            Some(target) if target.sub(&camera.position).len() > 0.0 => {
+                self.distance = target.sub(&camera.position).len();
+                self.target = target;
+            },
+            _ => self.target = camera.position.add(&forward.scale(self.distance)),
+        }
+        self.pending_yaw = 0.0;
+        self.pending_pitch = 0.0;
+        self.pending_roll = 0.0;
+        self.pending_zoom = 0.0;
+        self.pending_pan = (0.0, 0.0);
+        self.pending_dolly = 0.0;
+        self.velocity = math::Vector3::new();
+    }
+
+    // the point orbit keeps the camera aimed at
+    pub fn orbit_target(&self) -> Option<math::Vector3> {
+        match self.mode {
+            CameraControl::Orbit => Some(self.target),
+            _ => None,
+        }
+    }
+
+    // button as in PointerEvent.button, 0 left, 1 middle, 2 right
+    pub fn pointer_down(&mut self, x: f32, y: f32, button: u32) {
+        self.pointer = Some((button, x, y));
+    }
+
+    pub fn pointer_move(&mut self, x: f32, y: f32) {
+        if let Some((button, last_x, last_y)) = self.pointer {
+            self.drag(button, x - last_x, y - last_y);
+            self.pointer = Some((button, x, y));
+        }
+    }
+
+    pub fn pointer_up(&mut self, button: u32) {
+        if matches!(self.pointer, Some((b, _, _)) if b == button) {
+            self.pointer = None;
+        }
+    }
+
+    // WheelEvent.deltaY in pixels, positive scrolls towards the user
+    pub fn wheel(&mut self, delta: f32) {
+        match self.mode {
+            CameraControl::Orbit => self.pending_zoom += delta * 0.001,
+            _ => self.pending_dolly -= delta * 0.001 * self.move_speed,
+        }
+    }
+
+    pub fn touch_start(&mut self, id: i32, x: f32, y: f32) {
+        if let Some(slot) = self.touches.iter_mut().find(|t| t.is_none()) {
+            *slot = Some((id, x, y));
+        }
+    }
+
+    // one finger drags like the left button, two pinch to zoom and move to pan
+    pub fn touch_move(&mut self, id: i32, x: f32, y: f32) {
+        let index = match self.touches.iter().position(|t| matches!(t, Some((i, _, _)) if *i == id)) {
+            Some(index) => index,
+            None => return,
+        };
+        let (_, last_x, last_y) = self.touches[index].unwrap();
+        match self.touches[1 - index] {
+            None => self.drag(0, x - last_x, y - last_y),
+            Some((_, other_x, other_y)) => {
+                let spacing = |x: f32, y: f32| ((x - other_x).powi(2) + (y - other_y).powi(2)).sqrt().max(1.0);
+                let pinch = (spacing(last_x, last_y) / spacing(x, y)).ln();
+                // the midpoint moves half as far as the finger
+                let (dx, dy) = ((x - last_x) / 2.0, (y - last_y) / 2.0);
+                match self.mode {
+                    CameraControl::Orbit => {
+                        self.pending_zoom += pinch;
+                        self.pending_pan = (self.pending_pan.0 + dx, self.pending_pan.1 + dy);
+                    },
+                    _ => self.pending_dolly -= pinch * self.move_speed,
+                }
+            },
+        }
+        self.touches[index] = Some((id, x, y));
+    }
+
+    pub fn touch_end(&mut self, id: i32) {
+        for touch in self.touches.iter_mut() {
+            if matches!(touch, Some((i, _, _)) if *i == id) {
+                *touch = None;
+            }
+        }
+    }
+
+    // KeyboardEvent.code, so the keys stay in place on any layout
+    pub fn key_down(&mut self, code: &str) {
+        if let Some(key) = self.keys.get_mut(code) {
+            *key = true;
+        }
+    }
+
+    pub fn key_up(&mut self, code: &str) {
+        if let Some(key) = self.keys.get_mut(code) {
+            *key = false;
+        }
+    }
+
+    fn drag(&mut self, button: u32, dx: f32, dy: f32) {
+        let pan = button != 0 || self.keys.shift;
+        if self.mode == CameraControl::Orbit && pan {
+            self.pending_pan = (self.pending_pan.0 + dx, self.pending_pan.1 + dy);
+        } else if button == 0 {
+            // dragging down looks down, in orbit the camera rises to do so
+            self.pending_yaw += dx * self.look_speed;
+            self.pending_pitch -= dy * self.look_speed;
+        }
+    }
+
+    // plays out queued motion for dt seconds. viewport_height is in the same
+    // pixels as the events, for panning at the speed of the pointer
+    pub fn update(&mut self, camera: &mut Camera, dt: f32, viewport_height: f32) {
+        if self.mode == CameraControl::None || dt <= 0.0 {
+            return;
+        }
+        // the part of queued motion to play this frame, the same over a
+        // second whatever the frame rate
+        let k = if self.damping > 0.0 { 1.0 - (-dt / self.damping).exp() } else { 1.0 };
+        let drain = |pending: &mut f32| {
+            let step = *pending * k;
+            *pending -= step;
+            step
+        };
+
+        let (key_x, key_y, key_z, key_roll) = self.keys.axes();
+        match self.mode {
+            CameraControl::None => {},
+            CameraControl::Orbit => {
+                // left and right keys circle, forward and back zoom
+                self.pending_yaw -= key_x * KEY_TURN_SPEED * dt;
+                self.pending_zoom -= key_z * dt;
+
+                self.yaw += drain(&mut self.pending_yaw);
+                self.pitch = (self.pitch + drain(&mut self.pending_pitch)).clamp(self.min_pitch, self.max_pitch);
+
+                let distance = (self.distance * drain(&mut self.pending_zoom).exp()).clamp(self.min_distance, self.max_distance);
+                if camera.projection == ProjectionMode::Orthographic {
+                    camera.orthographic_height *= distance / self.distance;
+                }
+                self.distance = distance;
+
+                // the target moves so the point under the pointer follows it
+                let scale = world_per_pixel(camera, self.distance, viewport_height);
+                let (pan_x, pan_y) = (drain(&mut self.pending_pan.0), drain(&mut self.pending_pan.1));
+                let (right, up, _) = camera.axes();
+                self.target = self.target.sub(&right.scale(pan_x * scale)).add(&up.scale(pan_y * scale));
+
+                camera.position = self.target.sub(&direction(self.yaw, self.pitch).scale(self.distance));
+                camera.look_at(&self.target);
+            },
+            CameraControl::FirstPerson => {
+                self.yaw += drain(&mut self.pending_yaw);
+                self.pitch = (self.pitch + drain(&mut self.pending_pitch)).clamp(-LOOK_LIMIT, LOOK_LIMIT);
+                let forward = direction(self.yaw, self.pitch);
+
+                // walking ignores pitch, right is up x forward
+                let ahead = direction(self.yaw, 0.0);
+                let right = math::Vector3 { x: ahead.z, y: 0.0, z: -ahead.x };
+                let wish = right.scale(key_x).add(&ahead.scale(key_z));
+                self.accelerate(&wish, k);
+                let dolly = ahead.scale(drain(&mut self.pending_dolly));
+
+                camera.position = camera.position.add(&self.velocity.scale(dt)).add(&dolly);
+                camera.look_at(&camera.position.add(&forward));
+            },
+            CameraControl::Fly => {
+                self.pending_roll += key_roll * KEY_TURN_SPEED * dt;
+                // turns in the camera's own axes, pitching up is negative around x
+                let local = |x: f32, y: f32, z: f32, angle: f32| math::Quaternion::from_axis_angle(&math::Vector3 { x, y, z }, angle);
+                let turn = local(0.0, 1.0, 0.0, drain(&mut self.pending_yaw))
+                    .multiply(&local(1.0, 0.0, 0.0, -drain(&mut self.pending_pitch)))
+                    .multiply(&local(0.0, 0.0, 1.0, drain(&mut self.pending_roll)));
+                camera.orientation = camera.orientation.multiply(&turn).normal();
+
+                let (right, up, forward) = camera.axes();
+                camera.up = up;
+                let wish = right.scale(key_x).add(&up.scale(key_y)).add(&forward.scale(key_z));
+                self.accelerate(&wish, k);
+                let dolly = forward.scale(drain(&mut self.pending_dolly));
+                camera.position = camera.position.add(&self.velocity.scale(dt)).add(&dolly);
+            },
+        }
+    }
+
+    // eases velocity towards move_speed along wish, or to rest without keys
+    fn accelerate(&mut self, wish: &math::Vector3, k: f32) {
+        let wish = if wish.len() > 0.0 { wish.normal().scale(self.move_speed) } else { math::Vector3::new() };
+        self.velocity = wish.lerp(&self.velocity, k);
+    }
+}
+
+// view direction for a yaw and pitch, yaw turns from +z towards +x
+fn direction(yaw: f32, pitch: f32) -> math::Vector3 {
+    math::Vector3 { x: yaw.sin() * pitch.cos(), y: pitch.sin(), z: yaw.cos() * pitch.cos() }
+}
+
+// world size of a screen pixel at distance along the view
+fn world_per_pixel(camera: &Camera, distance: f32, viewport_height: f32) -> f32 {
+    let height = match camera.projection {
+        ProjectionMode::Perspective => 2.0 * distance * (camera.field_of_vision.to_radians() / 2.0).tan(),
+        ProjectionMode::Orthographic => camera.orthographic_height,
+        ProjectionMode::OffAxis => (camera.window[3] - camera.window[2]) * distance / camera.near,
+    };
+    height / viewport_height.max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: math::Vector3, b: math::Vector3) -> bool {
+        a.sub(&b).len() < 1e-3
+    }
+
+    fn orbiting(camera: &mut Camera) -> CameraControls {
+        let mut controls = CameraControls::new();
+        controls.mode = CameraControl::Orbit;
+        controls.damping = 0.0;
+        camera.position = math::Vector3 { x: 0.0, y: 0.0, z: 10.0 };
+        camera.look_at(&math::Vector3::new());
+        controls.sync(camera, Some(math::Vector3::new()));
+        controls
+    }
+
+    #[test]
+    fn test_orbit_drag_and_zoom() {
+        let mut camera = Camera::new();
+        let mut controls = orbiting(&mut camera);
+
+        // dragging right turns the scene with the pointer, so the camera goes
+        // round to its left. looking down -z that is +x
+        controls.pointer_down(0.0, 0.0, 0);
+        controls.pointer_move(FRAC_PI_2 / controls.look_speed, 0.0);
+        controls.pointer_up(0);
+        controls.update(&mut camera, 0.016, 600.0);
+        assert!(close(camera.position, math::Vector3 { x: 10.0, y: 0.0, z: 0.0 }));
+        assert!(close(camera.axes().2, math::Vector3 { x: -1.0, y: 0.0, z: 0.0 }));
+
+        // dragging far down stops at the pitch limit above the target
+        controls.pointer_down(0.0, 0.0, 0);
+        controls.pointer_move(0.0, 10000.0);
+        controls.update(&mut camera, 0.016, 600.0);
+        assert!((camera.position.y - 10.0 * LOOK_LIMIT.sin()).abs() < 1e-3);
+
+        // zoom stays within the limits
+        controls.min_distance = 2.0;
+        controls.wheel(-100000.0);
+        controls.update(&mut camera, 0.016, 600.0);
+        assert!((camera.position.len() - 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_orbit_pan_and_pinch() {
+        let mut camera = Camera::new();
+        let mut controls = orbiting(&mut camera);
+
+        // right drag by the whole viewport height moves the view that far
+        controls.pointer_down(0.0, 0.0, 2);
+        controls.pointer_move(0.0, 600.0);
+        controls.update(&mut camera, 0.016, 600.0);
+        let height = 2.0 * 10.0 * (30.0f32).to_radians().tan();
+        assert!(close(controls.target, math::Vector3 { x: 0.0, y: height, z: 0.0 }));
+        assert_eq!(controls.orbit_target(), Some(controls.target));
+
+        // spreading two fingers to twice the spacing halves the distance
+        controls.touch_start(1, 100.0, 100.0);
+        controls.touch_start(2, 200.0, 100.0);
+        controls.touch_move(2, 300.0, 100.0);
+        controls.update(&mut camera, 0.016, 600.0);
+        assert!((camera.position.sub(&controls.target).len() - 5.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_first_person_keys_and_damping() {
+        let mut camera = Camera::new();
+        camera.position = math::Vector3 { x: 0.0, y: 2.0, z: 0.0 };
+        camera.look_at(&math::Vector3 { x: 0.0, y: 0.0, z: 5.0 });
+        let mut controls = CameraControls::new();
+        controls.mode = CameraControl::FirstPerson;
+        controls.sync(&camera, None);
+
+        // walking forward while looking down stays at the same height, easing up to speed
+        controls.key_down("KeyW");
+        controls.update(&mut camera, 0.05, 600.0);
+        let first = camera.position.z;
+        assert!(first > 0.0 && first < controls.move_speed * 0.05);
+        for _ in 0..100 {
+            controls.update(&mut camera, 0.05, 600.0);
+        }
+        assert!((camera.position.y - 2.0).abs() < 1e-5);
+        assert!((controls.velocity.z - controls.move_speed).abs() < 1e-3);
+
+        // and coasts to a stop after letting go
+        controls.key_up("KeyW");
+        let released = camera.position.z;
+        for _ in 0..100 {
+            controls.update(&mut camera, 0.05, 600.0);
+        }
+        assert!(camera.position.z > released && controls.velocity.len() < 1e-3);
+    }
+
+    #[test]
+    fn test_fly_roll() {
+        let mut camera = Camera::new();
+        let mut controls = CameraControls::new();
+        controls.mode = CameraControl::Fly;
+        controls.damping = 0.0;
+        controls.sync(&camera, None);
+
+        // q lifts the right side
+        controls.key_down("KeyQ");
+        controls.update(&mut camera, FRAC_PI_2 / KEY_TURN_SPEED, 600.0);
+        controls.key_up("KeyQ");
+        assert!(close(camera.axes().0, math::Vector3 { x: 0.0, y: 1.0, z: 0.0 }));
+
+        // up is now towards +x, the camera's old left
+        controls.key_down("Space");
+        controls.update(&mut camera, 1.0, 600.0);
+        assert!(close(camera.position, math::Vector3 { x: controls.move_speed, y: 0.0, z: 1.0 }));
+    }
+}
@@ -0,0 +1,194 @@
+use std::f32::consts::PI;
+use std::fmt;
+use crate::math;
+use super::{Color, Texture, TextureFilter, TextureWrap};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CubeMapError {
+    // data length doesn't match six size x size rgba faces
+    WrongSize { expected: usize, found: usize },
+    // equirectangular panoramas are twice as wide as they are high
+    NotEquirectangular { width: u32, height: u32 },
+}
+
+impl fmt::Display for CubeMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CubeMapError::WrongSize { expected, found } => write!(f, "expected {} bytes of face data, found {}", expected, found),
+            CubeMapError::NotEquirectangular { width, height } => write!(f, "{}x{} is not a 2:1 panorama", width, height),
+        }
+    }
+}
+
+// six square faces in the order +x, -x, +y, -y, +z, -z, laid out like
+// opengl cube maps with the first row of each face at the top
+#[derive(Debug, Clone)]
+pub struct CubeMap {
+    pub faces: Vec<Texture>,
+}
+
+impl CubeMap {
+    // data is the six faces one after another
+    pub fn from_faces(data: &[u8], size: u32) -> Result<CubeMap, CubeMapError> {
+        let face_len = (size * size * 4) as usize;
+        if size == 0 || data.len() != face_len * 6 {
+            return Err(CubeMapError::WrongSize { expected: face_len * 6, found: data.len() });
+        }
+        let faces = data.chunks_exact(face_len).map(|face| {
+            let mut texture = Texture::from_rgba(face.to_vec(), size, size);
+            texture.wrap = TextureWrap::ClampToEdge;
+            texture
+        }).collect();
+        Ok(CubeMap { faces })
+    }
+
+    // resamples a latitude / longitude panorama, -z is the middle of the image
+    pub fn from_equirectangular(panorama: &Texture, size: u32) -> Result<CubeMap, CubeMapError> {
+        if panorama.width == 0 || panorama.width != panorama.height * 2 || size == 0 {
+            return Err(CubeMapError::NotEquirectangular { width: panorama.width, height: panorama.height });
+        }
+        let mut data = Vec::with_capacity((size * size * 4 * 6) as usize);
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let uv = equirectangular_uv(&face_direction(face, s, t));
+                    let c = panorama.sample(TextureFilter::Bilinear, uv.x, uv.y, 0.0);
+                    data.extend_from_slice(&[c.r, c.g, c.b, c.a]);
+                }
+            }
+        }
+        CubeMap::from_faces(&data, size)
+    }
+
+    pub fn sample(&self, filter: TextureFilter, direction: &math::Vector3) -> Color {
+        let (face, u, v) = face_coordinates(direction);
+        self.faces[face].sample(filter, u, v, 0.0)
+    }
+}
+
+// face index and 0..1 coordinates for a direction, by its major axis
+pub fn face_coordinates(d: &math::Vector3) -> (usize, f32, f32) {
+    let (ax, ay, az) = (d.x.abs(), d.y.abs(), d.z.abs());
+    let (face, s, t, major) = if ax >= ay && ax >= az {
+        if d.x > 0.0 { (0, -d.z, -d.y, ax) } else { (1, d.z, -d.y, ax) }
+    } else if ay >= az {
+        if d.y > 0.0 { (2, d.x, d.z, ay) } else { (3, d.x, -d.z, ay) }
+    } else if d.z > 0.0 {
+        (4, d.x, -d.y, az)
+    } else {
+        (5, -d.x, -d.y, az)
+    };
+    if major == 0.0 {
+        return (face, 0.5, 0.5);
+    }
+    (face, (s / major + 1.0) * 0.5, (t / major + 1.0) * 0.5)
+}
+
+// inverse of face_coordinates, s and t in -1..1
+fn face_direction(face: usize, s: f32, t: f32) -> math::Vector3 {
+    let (x, y, z) = match face {
+        0 => (1.0, -t, -s),
+        1 => (-1.0, -t, s),
+        2 => (s, 1.0, t),
+        3 => (s, -1.0, -t),
+        4 => (s, -t, 1.0),
+        _ => (-s, -t, -1.0),
+    };
+    math::Vector3 { x, y, z }.normal()
+}
+
+fn equirectangular_uv(d: &math::Vector3) -> math::Point {
+    let longitude = d.x.atan2(-d.z);
+    let latitude = d.y.clamp(-1.0, 1.0).asin();
+    math::Point { x: 0.5 + longitude / (2.0 * PI), y: 0.5 - latitude / PI }
+}
+
+// incident points at the surface, normal away from it
+pub fn reflect(incident: &math::Vector3, normal: &math::Vector3) -> math::Vector3 {
+    incident.sub(&normal.scale(2.0 * normal.dot(incident)))
+}
+
+// eta is the ratio of refractive indexes, outside over inside.
+// total internal reflection falls back to the reflected direction
+pub fn refract(incident: &math::Vector3, normal: &math::Vector3, eta: f32) -> math::Vector3 {
+    let cos_i = normal.dot(incident);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        return reflect(incident, normal);
+    }
+    incident.scale(eta).sub(&normal.scale(eta * cos_i + k.sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid_faces(size: u32) -> Vec<u8> {
+        let mut data = vec![];
+        for face in 0..6u8 {
+            for _ in 0..size * size {
+                data.extend_from_slice(&[face * 40, 0, 0, 255]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_face_coordinates_roundtrip() {
+        for face in 0..6 {
+            let d = face_direction(face, 0.5, -0.25);
+            let (found, u, v) = face_coordinates(&d);
+            assert_eq!(found, face);
+            assert!((u - 0.75).abs() < 1e-5 && (v - 0.375).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_cube_map_sample() {
+        let cube = CubeMap::from_faces(&solid_faces(2), 2).unwrap();
+        let sample = |x, y, z| cube.sample(TextureFilter::Nearest, &math::Vector3 { x, y, z }).r;
+        assert_eq!(sample(1.0, 0.2, 0.1), 0);
+        assert_eq!(sample(0.0, -3.0, 0.5), 120);
+        assert_eq!(sample(0.1, 0.0, -1.0), 200);
+
+        assert_eq!(CubeMap::from_faces(&[0; 10], 2).err(), Some(CubeMapError::WrongSize { expected: 96, found: 10 }));
+    }
+
+    #[test]
+    fn test_cube_map_from_equirectangular() {
+        // left half red, right half blue, -z sits on the seam between them
+        let mut data = vec![];
+        for _ in 0..2 {
+            data.extend_from_slice(&[255, 0, 0, 255, 255, 0, 0, 255, 0, 0, 255, 255, 0, 0, 255, 255]);
+        }
+        let mut panorama = Texture::from_rgba(data, 4, 2);
+        panorama.wrap = TextureWrap::ClampToEdge;
+        let cube = CubeMap::from_equirectangular(&panorama, 4).unwrap();
+        // -x is a quarter of the way in, +x three quarters
+        let left = cube.sample(TextureFilter::Nearest, &math::Vector3 { x: -1.0, y: 0.0, z: 0.0 });
+        let right = cube.sample(TextureFilter::Nearest, &math::Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert_eq!((left.r, left.b), (255, 0));
+        assert_eq!((right.r, right.b), (0, 255));
+
+        assert!(CubeMap::from_equirectangular(&Texture::blank(), 4).is_err());
+    }
+
+    #[test]
+    fn test_reflect_refract() {
+        let n = math::Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+        let i = math::Vector3 { x: 1.0, y: -1.0, z: 0.0 }.normal();
+        let r = reflect(&i, &n);
+        assert!((r.x - i.x).abs() < 1e-6 && (r.y + i.y).abs() < 1e-6);
+
+        // same medium passes straight through
+        let t = refract(&i, &n, 1.0);
+        assert!((t.x - i.x).abs() < 1e-6 && (t.y - i.y).abs() < 1e-6);
+        // into glass bends towards the normal
+        assert!(refract(&i, &n, 1.0 / 1.5).x < i.x);
+        // out of glass at a grazing angle reflects
+        let grazing = math::Vector3 { x: 1.0, y: -0.1, z: 0.0 }.normal();
+        assert_eq!(refract(&grazing, &n, 1.5), reflect(&grazing, &n));
+    }
+}
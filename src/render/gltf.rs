@@ -0,0 +1,699 @@
+use std::collections::HashMap;
+use std::fmt;
+use base64::Engine;
+use serde_json::Value;
+use crate::math;
+use super::scene::{Face, Object, Transform};
+use super::material::Material;
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GltfError {
+    InvalidGlb(&'static str),
+    InvalidJson(String),
+    // a required property is missing or has the wrong type
+    InvalidProperty(String),
+    // an index pointing past the end of one of the top level arrays
+    InvalidReference { kind: &'static str, index: usize },
+    // an external uri the caller hasn't supplied
+    MissingResource(String),
+    Unsupported(String),
+    InvalidImage(String),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::InvalidGlb(reason) => write!(f, "invalid glb: {}", reason),
+            GltfError::InvalidJson(reason) => write!(f, "invalid json: {}", reason),
+            GltfError::InvalidProperty(path) => write!(f, "missing or invalid property {}", path),
+            GltfError::InvalidReference { kind, index } => write!(f, "{} {} does not exist", kind, index),
+            GltfError::MissingResource(uri) => write!(f, "missing resource '{}'", uri),
+            GltfError::Unsupported(what) => write!(f, "unsupported {}", what),
+            GltfError::InvalidImage(reason) => write!(f, "invalid image: {}", reason),
+        }
+    }
+}
+
+// one object per node, parent indexes point into the same list and always
+// come before their children
+pub struct GltfNode {
+    pub object: Object,
+    pub parent: Option<usize>,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub fn is_glb(data: &[u8]) -> bool {
+    read_u32(data, 0) == Some(GLB_MAGIC)
+}
+
+// splits a .glb into its json and optional binary chunk
+fn parse_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), GltfError> {
+    if !is_glb(data) {
+        return Err(GltfError::InvalidGlb("bad magic"));
+    }
+    if read_u32(data, 4) != Some(2) {
+        return Err(GltfError::Unsupported("glb version".to_string()));
+    }
+    let length = read_u32(data, 8).ok_or(GltfError::InvalidGlb("truncated header"))? as usize;
+    if length > data.len() {
+        return Err(GltfError::InvalidGlb("truncated file"));
+    }
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = read_u32(data, offset).unwrap() as usize;
+        let chunk_type = read_u32(data, offset + 4).unwrap();
+        let chunk = data.get(offset + 8..offset + 8 + chunk_length).ok_or(GltfError::InvalidGlb("truncated chunk"))?;
+        match chunk_type {
+            CHUNK_JSON if json.is_none() => json = Some(chunk),
+            CHUNK_BIN if bin.is_none() => bin = Some(chunk),
+            // unknown chunks are to be skipped
+            _ => {},
+        }
+        // chunks are padded to 4 bytes
+        offset += 8 + ((chunk_length + 3) & !3);
+    }
+
+    match json {
+        Some(json) => Ok((json, bin)),
+        None => Err(GltfError::InvalidGlb("no json chunk")),
+    }
+}
+
+fn decode_data_uri(uri: &str) -> Option<Vec<u8>> {
+    let rest = uri.strip_prefix("data:")?;
+    let comma = rest.find(',')?;
+    if !rest[..comma].ends_with(";base64") {
+        return None;
+    }
+    base64::engine::general_purpose::STANDARD.decode(&rest[comma + 1..]).ok()
+}
+
+// png or jpeg bytes to a texture, the format is sniffed from the data
+pub fn decode_image(data: &[u8]) -> Result<super::Texture, GltfError> {
+    if data.starts_with(&[0x89, b'P', b'N', b'G']) {
+        decode_png(data)
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        decode_jpeg(data)
+    } else {
+        Err(GltfError::InvalidImage("not a png or jpeg".to_string()))
+    }
+}
+
+fn decode_png(data: &[u8]) -> Result<super::Texture, GltfError> {
+    let mut decoder = png::Decoder::new(data);
+    // palettes and 16 bit channels become plain 8 bit
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| GltfError::InvalidImage(e.to_string()))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| GltfError::InvalidImage(e.to_string()))?;
+    buffer.truncate(info.buffer_size());
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&p| vec![p, p, p, 255]).collect(),
+        png::ColorType::Indexed => return Err(GltfError::InvalidImage("unexpanded palette".to_string())),
+    };
+    Ok(super::Texture::from_rgba(rgba, info.width, info.height))
+}
+
+fn decode_jpeg(data: &[u8]) -> Result<super::Texture, GltfError> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let pixels = decoder.decode().map_err(|e| GltfError::InvalidImage(e.to_string()))?;
+    let info = decoder.info().ok_or_else(|| GltfError::InvalidImage("no image info".to_string()))?;
+
+    let rgba = match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => pixels.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect(),
+        jpeg_decoder::PixelFormat::L8 => pixels.iter().flat_map(|&p| vec![p, p, p, 255]).collect(),
+        format => return Err(GltfError::Unsupported(format!("jpeg pixel format {:?}", format))),
+    };
+    Ok(super::Texture::from_rgba(rgba, info.width as u32, info.height as u32))
+}
+
+fn wrap_mode(mode: Option<u64>) -> super::TextureWrap {
+    match mode {
+        Some(33071) => super::TextureWrap::ClampToEdge,
+        Some(33648) => super::TextureWrap::MirroredRepeat,
+        _ => super::TextureWrap::Repeat,
+    }
+}
+
+// a typed view into one of the buffers
+struct Accessor<'a> {
+    data: &'a [u8],
+    offset: usize,
+    stride: usize,
+    count: usize,
+    component_type: u64,
+    normalized: bool,
+}
+
+impl<'a> Accessor<'a> {
+    fn component_size(component_type: u64) -> Option<usize> {
+        match component_type {
+            5120 | 5121 => Some(1),
+            5122 | 5123 => Some(2),
+            5125 | 5126 => Some(4),
+            _ => None,
+        }
+    }
+
+    fn component(&self, index: usize, component: usize) -> f32 {
+        let size = Accessor::component_size(self.component_type).unwrap();
+        let at = self.offset + index * self.stride + component * size;
+        let b = &self.data[at..at + size];
+        let (value, max) = match self.component_type {
+            5120 => (b[0] as i8 as f32, 127.0),
+            5121 => (b[0] as f32, 255.0),
+            5122 => (i16::from_le_bytes([b[0], b[1]]) as f32, 32767.0),
+            5123 => (u16::from_le_bytes([b[0], b[1]]) as f32, 65535.0),
+            5125 => (u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32, 1.0),
+            _ => return f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        };
+        // normalized integers map to 0..1 or -1..1
+        if self.normalized { (value / max).max(-1.0) } else { value }
+    }
+
+    // indexes are read as integers so large u32 values don't lose precision
+    fn index(&self, index: usize) -> usize {
+        let at = self.offset + index * self.stride;
+        let b = &self.data[at..];
+        match self.component_type {
+            5121 => b[0] as usize,
+            5123 => u16::from_le_bytes([b[0], b[1]]) as usize,
+            5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize,
+            _ => unreachable!("index accessors are checked to be unsigned integers"),
+        }
+    }
+}
+
+pub struct GltfLoader<'a> {
+    json: Value,
+    buffers: Vec<Vec<u8>>,
+    resources: &'a HashMap<String, Vec<u8>>,
+    // decoded on first use, a texture can be shared by several materials
+    textures: HashMap<usize, super::Texture>,
+}
+
+impl<'a> GltfLoader<'a> {
+    fn array(&self, name: &str) -> &[Value] {
+        self.json[name].as_array().map(|a| a.as_slice()).unwrap_or(&[])
+    }
+
+    fn element(&self, kind: &'static str, index: usize) -> Result<&Value, GltfError> {
+        self.array(kind).get(index).ok_or(GltfError::InvalidReference { kind, index })
+    }
+
+    fn usize_property(value: &Value, path: &str) -> Result<usize, GltfError> {
+        value.as_u64().map(|v| v as usize).ok_or_else(|| GltfError::InvalidProperty(path.to_string()))
+    }
+
+    fn resource(&self, uri: &str) -> Result<Vec<u8>, GltfError> {
+        if uri.starts_with("data:") {
+            return decode_data_uri(uri).ok_or_else(|| GltfError::Unsupported(format!("data uri '{}'", &uri[..uri.len().min(32)])));
+        }
+        self.resources.get(uri).cloned().ok_or_else(|| GltfError::MissingResource(uri.to_string()))
+    }
+
+    fn load_buffers(&mut self, bin: Option<&[u8]>) -> Result<(), GltfError> {
+        let mut buffers = vec![];
+        for (i, buffer) in self.array("buffers").iter().enumerate() {
+            let data = match buffer["uri"].as_str() {
+                Some(uri) => self.resource(uri)?,
+                // only the first buffer of a glb may leave out the uri
+                None if i == 0 => bin.map(|b| b.to_vec()).ok_or(GltfError::InvalidGlb("buffer 0 without binary chunk"))?,
+                None => return Err(GltfError::InvalidProperty(format!("buffers[{}].uri", i))),
+            };
+            buffers.push(data);
+        }
+        self.buffers = buffers;
+        Ok(())
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<&[u8], GltfError> {
+        let view = self.element("bufferViews", index)?;
+        let buffer = GltfLoader::usize_property(&view["buffer"], "bufferView.buffer")?;
+        let offset = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let length = GltfLoader::usize_property(&view["byteLength"], "bufferView.byteLength")?;
+        let invalid = GltfError::InvalidReference { kind: "bufferViews", index };
+        let end = offset.checked_add(length).ok_or_else(|| invalid.clone())?;
+        self.buffers.get(buffer)
+            .ok_or(GltfError::InvalidReference { kind: "buffers", index: buffer })?
+            .get(offset..end)
+            .ok_or(invalid)
+    }
+
+    // components is what the caller reads per element, the declared type has to match
+    fn accessor(&self, index: usize, components: usize) -> Result<Accessor<'_>, GltfError> {
+        let accessor = self.element("accessors", index)?;
+        if !accessor["sparse"].is_null() {
+            return Err(GltfError::Unsupported("sparse accessors".to_string()));
+        }
+        let declared = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            _ => return Err(GltfError::Unsupported(format!("accessor {} type", index))),
+        };
+        if declared != components {
+            return Err(GltfError::InvalidProperty(format!("accessors[{}].type", index)));
+        }
+        let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+        let size = Accessor::component_size(component_type)
+            .ok_or_else(|| GltfError::InvalidProperty(format!("accessors[{}].componentType", index)))?;
+        let count = GltfLoader::usize_property(&accessor["count"], "accessor.count")?;
+        let view_index = GltfLoader::usize_property(&accessor["bufferView"], "accessor.bufferView")?;
+        let data = self.buffer_view(view_index)?;
+        let stride = match self.element("bufferViews", view_index)?["byteStride"].as_u64() {
+            Some(stride) => stride as usize,
+            None => components * size,
+        };
+        let offset = accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+
+        // count, stride and offset come from the file, so this can overflow
+        let end = match count {
+            0 => Some(0),
+            _ => (count - 1).checked_mul(stride)
+                .and_then(|last| last.checked_add(offset))
+                .and_then(|last| last.checked_add(components * size)),
+        };
+        if end.is_none_or(|end| end > data.len()) {
+            return Err(GltfError::InvalidReference { kind: "accessors", index });
+        }
+        Ok(Accessor {
+            data,
+            offset,
+            stride,
+            count,
+            component_type,
+            normalized: accessor["normalized"].as_bool().unwrap_or(false),
+        })
+    }
+
+    fn texture(&mut self, index: usize) -> Result<super::Texture, GltfError> {
+        if let Some(texture) = self.textures.get(&index) {
+            return Ok(texture.clone());
+        }
+        let texture = self.element("textures", index)?;
+        let source = GltfLoader::usize_property(&texture["source"], "texture.source")?;
+        // Texture has one wrap mode for both axes, wrapT is ignored
+        let wrap = match texture["sampler"].as_u64() {
+            Some(sampler) => wrap_mode(self.element("samplers", sampler as usize)?["wrapS"].as_u64()),
+            None => super::TextureWrap::Repeat,
+        };
+
+        let image = self.element("images", source)?;
+        let data = match (image["uri"].as_str(), image["bufferView"].as_u64()) {
+            (Some(uri), _) => self.resource(uri)?,
+            (None, Some(view)) => self.buffer_view(view as usize)?.to_vec(),
+            _ => return Err(GltfError::InvalidProperty(format!("images[{}].uri", source))),
+        };
+        let mut decoded = decode_image(&data)?;
+        decoded.wrap = wrap;
+        self.textures.insert(index, decoded.clone());
+        Ok(decoded)
+    }
+
+    // metallic-roughness is approximated with the blinn-phong terms of Material
+    fn material(&mut self, index: usize) -> Result<Material, GltfError> {
+        let json = self.element("materials", index)?.clone();
+        let mut material = Material::new(json["name"].as_str().unwrap_or(""));
+        material.defined = true;
+
+        let pbr = &json["pbrMetallicRoughness"];
+        if let Some(factor) = pbr["baseColorFactor"].as_array() {
+            let c = |i: usize| factor.get(i).and_then(|v| v.as_f64()).unwrap_or(1.0) as f32;
+            material.diffuse = math::Vector3 { x: c(0), y: c(1), z: c(2) };
+            material.opacity = c(3);
+        }
+        let roughness = pbr["roughnessFactor"].as_f64().unwrap_or(1.0) as f32;
+        let smoothness = 1.0 - roughness;
+        material.specular = math::Vector3 { x: smoothness, y: smoothness, z: smoothness };
+        material.shininess = 2.0 + 254.0 * smoothness * smoothness;
+
+        if let Some(texture) = pbr["baseColorTexture"]["index"].as_u64() {
+            material.diffuse_texture = Some(self.texture(texture as usize)?);
+        }
+        if let Some(texture) = json["normalTexture"]["index"].as_u64() {
+            material.normal_texture = Some(self.texture(texture as usize)?);
+        }
+        Ok(material)
+    }
+
+    // all primitives of a mesh go into one object, one material each
+    fn mesh(&mut self, index: usize) -> Result<Object, GltfError> {
+        let mut obj = Object::new();
+        // faces without a material use the white default material of glTF
+        obj.texture = super::Texture::blank();
+        let primitives = self.element("meshes", index)?["primitives"].as_array().cloned().unwrap_or_default();
+
+        for primitive in primitives.iter() {
+            let mode = primitive["mode"].as_u64().unwrap_or(4);
+            if mode < 4 {
+                // points and lines have nothing to rasterize
+                continue;
+            }
+            let attributes = &primitive["attributes"];
+            let position_index = GltfLoader::usize_property(&attributes["POSITION"], "primitive.attributes.POSITION")?;
+
+            let base = obj.vertices.len();
+            let positions = self.accessor(position_index, 3)?;
+            for i in 0..positions.count {
+                obj.vertices.push(math::Vector4 {
+                    x: positions.component(i, 0),
+                    y: positions.component(i, 1),
+                    z: positions.component(i, 2),
+                    w: 1.0,
+                });
+            }
+            let count = positions.count;
+
+            // normals and uvs are padded so all three share the vertex index
+            let has_normals = match attributes["NORMAL"].as_u64() {
+                Some(normal_index) => {
+                    let normals = self.accessor(normal_index as usize, 3)?;
+                    obj.vertex_normals.resize(base, math::Vector4 { x: 0.0, y: 1.0, z: 0.0, w: 0.0 });
+                    for i in 0..count.min(normals.count) {
+                        obj.vertex_normals.push(math::Vector4 { x: normals.component(i, 0), y: normals.component(i, 1), z: normals.component(i, 2), w: 0.0 });
+                    }
+                    obj.vertex_normals.len() == base + count
+                },
+                None => false,
+            };
+            let has_uvs = match attributes["TEXCOORD_0"].as_u64() {
+                Some(uv_index) => {
+                    let uvs = self.accessor(uv_index as usize, 2)?;
+                    obj.uvs.resize(base, math::Point { x: 0.0, y: 0.0 });
+                    for i in 0..count.min(uvs.count) {
+                        // gltf has v pointing down, the obj convention used by Object points up
+                        obj.uvs.push(math::Point { x: uvs.component(i, 0), y: 1.0 - uvs.component(i, 1) });
+                    }
+                    obj.uvs.len() == base + count
+                },
+                None => false,
+            };
+
+            let material = match primitive["material"].as_u64() {
+                Some(material) => {
+                    // culling is per object, one double sided primitive turns it off
+                    if self.element("materials", material as usize)?["doubleSided"].as_bool() == Some(true) {
+                        obj.cull_mode = super::CullMode::None;
+                    }
+                    obj.materials.push(self.material(material as usize)?);
+                    Some(obj.materials.len() - 1)
+                },
+                None => None,
+            };
+
+            let indexes: Vec<usize> = match primitive["indices"].as_u64() {
+                Some(indices) => {
+                    let accessor = self.accessor(indices as usize, 1)?;
+                    // indices can only be unsigned bytes, shorts or ints
+                    if ![5121, 5123, 5125].contains(&accessor.component_type) {
+                        return Err(GltfError::InvalidProperty(format!("accessors[{}].componentType", indices)));
+                    }
+                    (0..accessor.count).map(|i| accessor.index(i)).collect()
+                },
+                None => (0..count).collect(),
+            };
+            if let Some(&bad) = indexes.iter().find(|&&i| i >= count) {
+                return Err(GltfError::InvalidReference { kind: "vertices", index: bad });
+            }
+
+            let triangles: Vec<[usize; 3]> = match mode {
+                4 => indexes.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect(),
+                // every other strip triangle is flipped to keep the winding
+                5 => (2..indexes.len()).map(|i| if i % 2 == 0 {
+                    [indexes[i - 2], indexes[i - 1], indexes[i]]
+                } else {
+                    [indexes[i - 1], indexes[i - 2], indexes[i]]
+                }).collect(),
+                6 => (2..indexes.len()).map(|i| [indexes[0], indexes[i - 1], indexes[i]]).collect(),
+                _ => return Err(GltfError::Unsupported(format!("primitive mode {}", mode))),
+            };
+
+            let index = |i: usize, present: bool| if present { (base + i) as i32 } else { -1 };
+            for [a, b, c] in triangles {
+                // same reversed winding as ObjLoader
+                obj.faces.push(Face {
+                    v0: index(c, true),
+                    v1: index(b, true),
+                    v2: index(a, true),
+                    vn0: index(c, has_normals),
+                    vn1: index(b, has_normals),
+                    vn2: index(a, has_normals),
+                    uv0: index(c, has_uvs),
+                    uv1: index(b, has_uvs),
+                    uv2: index(a, has_uvs),
+                    material,
+                    smoothing_group: 0,
+                });
+            }
+        }
+
+        if !obj.uvs.is_empty() {
+            obj.uv_source = super::UvSource::Mesh;
+        }
+        if obj.has_normal_maps() {
+            obj.generate_tangents();
+        }
+        Ok(obj)
+    }
+
+    fn node_transform(node: &Value) -> Transform {
+        let numbers = |name: &str| -> Option<Vec<f32>> {
+            node[name].as_array().map(|a| a.iter().map(|v| v.as_f64().unwrap_or(0.0) as f32).collect())
+        };
+
+        // gltf matrices are column major for column vectors, which is the
+        // same memory layout as a row major matrix for row vectors
+        if let Some(m) = numbers("matrix").filter(|m| m.len() == 16) {
+            let mut matrix = math::Matrix4::zero();
+            for (i, value) in m.iter().enumerate() {
+                matrix.m[i / 4][i % 4] = *value;
+            }
+            return Transform::from_matrix(&matrix);
+        }
+
+        let mut transform = match numbers("rotation").filter(|r| r.len() == 4) {
+            Some(q) => Transform::from_matrix(&math::Matrix4::from_quaternion(q[0], q[1], q[2], q[3])),
+            None => Transform::new(),
+        };
+        if let Some(t) = numbers("translation").filter(|t| t.len() == 3) {
+            transform.position = math::Vector3 { x: t[0], y: t[1], z: t[2] };
+        }
+        if let Some(s) = numbers("scale").filter(|s| s.len() == 3) {
+            transform.scale = math::Vector3 { x: s[0], y: s[1], z: s[2] };
+        }
+        transform
+    }
+
+    fn add_node(&mut self, index: usize, parent: Option<usize>, nodes: &mut Vec<GltfNode>, depth: usize) -> Result<(), GltfError> {
+        // nodes must form a tree, this also stops cycles in broken files
+        if depth > self.array("nodes").len() {
+            return Err(GltfError::InvalidProperty("nodes form a cycle".to_string()));
+        }
+        let node = self.element("nodes", index)?.clone();
+        let mut object = match node["mesh"].as_u64() {
+            Some(mesh) => self.mesh(mesh as usize)?,
+            // a node without a mesh is kept as an empty object to hold the transform
+            None => Object::new(),
+        };
+        object.transform = GltfLoader::node_transform(&node);
+
+        nodes.push(GltfNode {
+            object,
+            parent,
+        });
+        let this = nodes.len() - 1;
+
+        if let Some(children) = node["children"].as_array() {
+            for child in children {
+                let child = GltfLoader::usize_property(child, "node.children")?;
+                self.add_node(child, Some(this), nodes, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn root_nodes(&self) -> Vec<usize> {
+        let scene = self.json["scene"].as_u64().unwrap_or(0) as usize;
+        if let Some(roots) = self.array("scenes").get(scene).and_then(|s| s["nodes"].as_array()) {
+            return roots.iter().filter_map(|n| n.as_u64()).map(|n| n as usize).collect();
+        }
+        // without scenes every node that isn't a child is a root
+        let nodes = self.array("nodes");
+        let children: Vec<u64> = nodes.iter()
+            .filter_map(|n| n["children"].as_array())
+            .flat_map(|c| c.iter().filter_map(|v| v.as_u64()))
+            .collect();
+        (0..nodes.len()).filter(|i| !children.contains(&(*i as u64))).collect()
+    }
+
+    fn parse_json(json: &[u8]) -> Result<Value, GltfError> {
+        let json: Value = serde_json::from_slice(json).map_err(|e| GltfError::InvalidJson(e.to_string()))?;
+        let version = json["asset"]["version"].as_str().unwrap_or("");
+        if !version.starts_with("2.") {
+            return Err(GltfError::Unsupported(format!("gltf version '{}'", version)));
+        }
+        if let Some(required) = json["extensionsRequired"].as_array().and_then(|e| e.first()) {
+            return Err(GltfError::Unsupported(format!("extension {}", required)));
+        }
+        Ok(json)
+    }
+
+    // uris of the buffers and images that aren't embedded, for the caller to fetch
+    pub fn external_resources(data: &[u8]) -> Result<Vec<String>, GltfError> {
+        let json = if is_glb(data) { parse_glb(data)?.0 } else { data };
+        let json = GltfLoader::parse_json(json)?;
+        let mut uris = vec![];
+        for kind in ["buffers", "images"].iter() {
+            for element in json[*kind].as_array().map(|a| a.as_slice()).unwrap_or(&[]) {
+                if let Some(uri) = element["uri"].as_str() {
+                    if !uri.starts_with("data:") && !uris.iter().any(|u| u == uri) {
+                        uris.push(uri.to_string());
+                    }
+                }
+            }
+        }
+        Ok(uris)
+    }
+
+    // loads a .gltf or .glb, external files are looked up in resources by uri
+    pub fn load_gltf(data: &[u8], resources: &HashMap<String, Vec<u8>>) -> Result<Vec<GltfNode>, GltfError> {
+        let (json, bin) = if is_glb(data) { parse_glb(data)? } else { (data, None) };
+        let mut loader = GltfLoader {
+            json: GltfLoader::parse_json(json)?,
+            buffers: vec![],
+            resources,
+            textures: HashMap::new(),
+        };
+        loader.load_buffers(bin)?;
+
+        let mut nodes = vec![];
+        for root in loader.root_nodes() {
+            loader.add_node(root, None, &mut nodes, 0)?;
+        }
+        Ok(nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE_GLTF: &str = include_str!("../../tests/fixtures/triangle.gltf");
+    const TRIANGLE_GLB: &[u8] = include_bytes!("../../tests/fixtures/triangle.glb");
+
+    fn check_triangle_scene(nodes: &[GltfNode]) {
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].parent, None);
+        assert!(nodes[0].object.vertices.is_empty());
+        assert_eq!(nodes[0].object.transform.position, math::Vector3 { x: 1.0, y: 2.0, z: 3.0 });
+
+        let child = &nodes[1];
+        assert_eq!(child.parent, Some(0));
+        // quarter turn around z, x ends up on y
+        let x = math::Vector4 { x: 1.0, y: 0.0, z: 0.0, w: 1.0 }.multiply(&child.object.transform.matrix());
+        assert!(x.x.abs() < 1e-5 && (x.y - 1.0).abs() < 1e-5);
+
+        let obj = &child.object;
+        assert_eq!(obj.vertices.len(), 3);
+        assert_eq!(obj.vertex_normals.len(), 3);
+        assert_eq!(obj.uvs[2], math::Point { x: 0.0, y: 0.0 });
+        assert_eq!(obj.faces.len(), 1);
+        assert_eq!((obj.faces[0].v0, obj.faces[0].v1, obj.faces[0].v2), (2, 1, 0));
+        assert_eq!(obj.faces[0].material, Some(0));
+
+        let material = &obj.materials[0];
+        assert_eq!(material.diffuse, math::Vector3 { x: 1.0, y: 0.5, z: 0.25 });
+        let texture = material.diffuse_texture.as_ref().unwrap();
+        assert_eq!((texture.width, texture.height), (2, 2));
+        assert_eq!(&texture.data[0..4], &[255, 0, 0, 255]);
+        assert_eq!(texture.wrap, super::super::TextureWrap::ClampToEdge);
+    }
+
+    #[test]
+    fn test_load_gltf() {
+        let nodes = GltfLoader::load_gltf(TRIANGLE_GLTF.as_bytes(), &HashMap::new()).unwrap();
+        check_triangle_scene(&nodes);
+    }
+
+    #[test]
+    fn test_load_glb() {
+        assert!(is_glb(TRIANGLE_GLB));
+        let nodes = GltfLoader::load_gltf(TRIANGLE_GLB, &HashMap::new()).unwrap();
+        check_triangle_scene(&nodes);
+    }
+
+    #[test]
+    fn test_external_resources() {
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "mesh.bin", "byteLength": 36 }],
+            "images": [{ "uri": "albedo.png" }, { "uri": "data:image/png;base64,AAAA" }]
+        }"#;
+        let uris = GltfLoader::external_resources(gltf.as_bytes()).unwrap();
+        assert_eq!(uris, vec!["mesh.bin".to_string(), "albedo.png".to_string()]);
+
+        let error = GltfLoader::load_gltf(gltf.as_bytes(), &HashMap::new()).err();
+        assert_eq!(error, Some(GltfError::MissingResource("mesh.bin".to_string())));
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let v1 = r#"{ "asset": { "version": "1.0" } }"#;
+        assert!(matches!(GltfLoader::load_gltf(v1.as_bytes(), &HashMap::new()), Err(GltfError::Unsupported(_))));
+        assert!(matches!(GltfLoader::load_gltf(b"{ nope", &HashMap::new()), Err(GltfError::InvalidJson(_))));
+        assert_eq!(parse_glb(b"glTF\x02\x00\x00\x00\xff\x00\x00\x00").err(), Some(GltfError::InvalidGlb("truncated file")));
+    }
+
+    // one mesh over a 36 byte buffer of zeros
+    fn zeros_gltf(accessors: &str, primitive: &str) -> String {
+        format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "uri": "data:application/octet-stream;base64,{}", "byteLength": 36 }}],
+            "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+            "accessors": [{}],
+            "meshes": [{{ "primitives": [{}] }}],
+            "nodes": [{{ "mesh": 0 }}]
+        }}"#, "A".repeat(48), accessors, primitive)
+    }
+
+    #[test]
+    fn test_rejects_mismatched_accessor_type() {
+        // three scalars fit the buffer, reading them as vec3 positions wouldn't
+        let gltf = zeros_gltf(r#"{ "bufferView": 0, "byteOffset": 24, "componentType": 5126, "count": 3, "type": "SCALAR" }"#,
+            r#"{ "attributes": { "POSITION": 0 } }"#);
+        assert_eq!(GltfLoader::load_gltf(gltf.as_bytes(), &HashMap::new()).err(), Some(GltfError::InvalidProperty("accessors[0].type".to_string())));
+    }
+
+    #[test]
+    fn test_rejects_huge_accessor_count() {
+        // (count - 1) * stride would wrap around instead of running past the buffer
+        let gltf = zeros_gltf(&format!(r#"{{ "bufferView": 0, "componentType": 5126, "count": {}, "type": "VEC3" }}"#, usize::MAX / 4),
+            r#"{ "attributes": { "POSITION": 0 } }"#);
+        assert_eq!(GltfLoader::load_gltf(gltf.as_bytes(), &HashMap::new()).err(), Some(GltfError::InvalidReference { kind: "accessors", index: 0 }));
+    }
+
+    #[test]
+    fn test_rejects_float_indices() {
+        // the last float index ends right at the end of the buffer
+        let gltf = zeros_gltf(r#"{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+            { "bufferView": 0, "byteOffset": 24, "componentType": 5126, "count": 3, "type": "SCALAR" }"#,
+            r#"{ "attributes": { "POSITION": 0 }, "indices": 1 }"#);
+        assert_eq!(GltfLoader::load_gltf(gltf.as_bytes(), &HashMap::new()).err(), Some(GltfError::InvalidProperty("accessors[1].componentType".to_string())));
+    }
+}
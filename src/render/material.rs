@@ -0,0 +1,222 @@
+use crate::math;
+
+#[derive(Debug)]
+pub struct Material {
+    pub name: String,
+    // Ka, Kd, Ks as rgb in 0..1
+    pub ambient: math::Vector3,
+    pub diffuse: math::Vector3,
+    pub specular: math::Vector3,
+    // Ns
+    pub shininess: f32,
+    // d, 1.0 is fully opaque
+    pub opacity: f32,
+    // texture file names as written in the .mtl
+    pub diffuse_map: Option<String>,
+    pub normal_map: Option<String>,
+    // image data resolved for the maps above
+    pub diffuse_texture: Option<super::Texture>,
+    pub normal_texture: Option<super::Texture>,
+    // false until a newmtl with this name has been loaded
+    pub defined: bool,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            ambient: math::Vector3 { x: 1.0, y: 1.0, z: 1.0 },
+            diffuse: math::Vector3 { x: 1.0, y: 1.0, z: 1.0 },
+            specular: math::Vector3 { x: 0.0, y: 0.0, z: 0.0 },
+            shininess: 0.0,
+            opacity: 1.0,
+            diffuse_map: None,
+            normal_map: None,
+            diffuse_texture: None,
+            normal_texture: None,
+            defined: false,
+        }
+    }
+
+    // texture files referenced by this material that have no image data yet
+    pub fn missing_textures(&self) -> Vec<String> {
+        let mut missing = vec![];
+        if let (Some(name), None) = (&self.diffuse_map, &self.diffuse_texture) {
+            missing.push(name.clone());
+        }
+        if let (Some(name), None) = (&self.normal_map, &self.normal_texture) {
+            missing.push(name.clone());
+        }
+        missing
+    }
+
+    // takes the definition from a parsed .mtl, keeping already resolved textures
+    // if the map file names didn't change
+    pub fn update_from(&mut self, other: Material) {
+        let diffuse_texture = if other.diffuse_map == self.diffuse_map { self.diffuse_texture.take() } else { None };
+        let normal_texture = if other.normal_map == self.normal_map { self.normal_texture.take() } else { None };
+        *self = other;
+        self.diffuse_texture = diffuse_texture;
+        self.normal_texture = normal_texture;
+    }
+}
+
+pub struct MtlLoader {
+
+}
+
+impl MtlLoader {
+    fn parse_color(parts: &[&str]) -> Option<math::Vector3> {
+        if parts.is_empty() {
+            return None;
+        }
+        let r = parts[0].parse::<f32>().unwrap_or(0.0);
+        // a single value means grey
+        if parts.len() < 3 {
+            return Some(math::Vector3 { x: r, y: r, z: r });
+        }
+        let g = parts[1].parse::<f32>().unwrap_or(0.0);
+        let b = parts[2].parse::<f32>().unwrap_or(0.0);
+        Some(math::Vector3 { x: r, y: g, z: b })
+    }
+
+    fn parse_scalar(parts: &[&str]) -> Option<f32> {
+        parts.first().and_then(|p| p.parse::<f32>().ok())
+    }
+
+    // map statements can have options before the file name, e.g. "-bm 0.5 bump.png"
+    fn parse_map(parts: &[&str]) -> Option<String> {
+        let mut i = 0;
+        while i < parts.len() && parts[i].starts_with('-') {
+            i += match parts[i] {
+                "-o" | "-s" | "-t" => 4,
+                "-mm" => 3,
+                _ => 2,
+            };
+        }
+        if i >= parts.len() {
+            return None;
+        }
+        Some(parts[i..].join(" "))
+    }
+
+    pub fn load_mtl(file_as_string: &str) -> Vec<Material> {
+        let mut materials: Vec<Material> = vec![];
+
+        for line in file_as_string.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+
+            if parts.is_empty() {
+                continue;
+            }
+
+            let entry_type = parts[0];
+            let entry_data = &parts[1..];
+
+            if entry_type == "newmtl" {
+                let mut material = Material::new(&entry_data.join(" "));
+                material.defined = true;
+                materials.push(material);
+                continue;
+            }
+
+            let material = match materials.last_mut() {
+                Some(m) => m,
+                None => continue,
+            };
+
+            match entry_type {
+                "Ka" => {
+                    if let Some(c) = MtlLoader::parse_color(entry_data) {
+                        material.ambient = c;
+                    }
+                },
+                "Kd" => {
+                    if let Some(c) = MtlLoader::parse_color(entry_data) {
+                        material.diffuse = c;
+                    }
+                },
+                "Ks" => {
+                    if let Some(c) = MtlLoader::parse_color(entry_data) {
+                        material.specular = c;
+                    }
+                },
+                "Ns" => {
+                    if let Some(ns) = MtlLoader::parse_scalar(entry_data) {
+                        material.shininess = ns;
+                    }
+                },
+                "d" => {
+                    if let Some(d) = MtlLoader::parse_scalar(entry_data) {
+                        material.opacity = d;
+                    }
+                },
+                "Tr" => {
+                    if let Some(tr) = MtlLoader::parse_scalar(entry_data) {
+                        material.opacity = 1.0 - tr;
+                    }
+                },
+                "map_Kd" => {
+                    material.diffuse_map = MtlLoader::parse_map(entry_data);
+                },
+                "map_Bump" | "map_bump" | "bump" | "norm" => {
+                    material.normal_map = MtlLoader::parse_map(entry_data);
+                },
+                _ => {
+
+                }
+            }
+        }
+        materials
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_map() {
+        assert_eq!(MtlLoader::parse_map(&["diffuse.png"]), Some("diffuse.png".to_string()));
+        assert_eq!(MtlLoader::parse_map(&["-bm", "0.5", "bump.png"]), Some("bump.png".to_string()));
+        assert_eq!(MtlLoader::parse_map(&["-s", "1", "1", "1", "my", "file.png"]), Some("my file.png".to_string()));
+        assert_eq!(MtlLoader::parse_map(&["-bm", "0.5"]), None);
+    }
+
+    #[test]
+    fn test_load_mtl() {
+        let mtl = "
+# two materials
+newmtl red
+Ka 0.1 0.1 0.1
+Kd 1.0 0.0 0.0
+Ks 0.5
+Ns 64
+d 0.5
+map_Kd red.png
+map_Bump -bm 2 red_normal.png
+
+newmtl glass
+Kd 0.8 0.9 1.0
+Tr 0.75
+";
+        let materials = MtlLoader::load_mtl(mtl);
+        assert_eq!(materials.len(), 2);
+
+        let red = &materials[0];
+        assert_eq!(red.name, "red");
+        assert!(red.defined);
+        assert_eq!(red.ambient, math::Vector3 { x: 0.1, y: 0.1, z: 0.1 });
+        assert_eq!(red.diffuse, math::Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert_eq!(red.specular, math::Vector3 { x: 0.5, y: 0.5, z: 0.5 });
+        assert_eq!(red.shininess, 64.0);
+        assert_eq!(red.opacity, 0.5);
+        assert_eq!(red.diffuse_map, Some("red.png".to_string()));
+        assert_eq!(red.normal_map, Some("red_normal.png".to_string()));
+        assert_eq!(red.missing_textures(), vec!["red.png".to_string(), "red_normal.png".to_string()]);
+
+        let glass = &materials[1];
+        assert_eq!(glass.opacity, 0.25);
+        assert_eq!(glass.diffuse_map, None);
+    }
+}
@@ -1,8 +1,17 @@
 
+use std::fmt;
 use std::mem;
+use wasm_bindgen::prelude::*;
 use crate::math;
 
 pub mod scene;
+pub mod material;
+pub mod gltf;
+pub mod stl;
+pub mod ply;
+pub mod cubemap;
+pub mod clip;
+pub mod controls;
 
 pub struct RenderTarget {
     pub width: u32,
@@ -11,6 +20,13 @@ pub struct RenderTarget {
     pub depth: Vec<f32>
 }
 
+impl RenderTarget {
+    // the camera projection always follows the target's shape
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+}
+
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -18,6 +34,147 @@ pub struct Color {
     pub a: u8,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureInterpolation {
+    // interpolate u/v linearly in screen space, cheaper but warps
+    Affine,
+    // interpolate u/w, v/w and 1/w and divide per pixel
+    PerspectiveCorrect,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShadingMode {
+    // texture only, no lighting
+    Unlit,
+    // one lighting result per face
+    Flat,
+    // lighting per vertex, interpolated across the face
+    Gouraud,
+    // normal and position interpolated, lighting per pixel
+    Phong,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UvSource {
+    // vt coordinates from the mesh
+    Mesh,
+    // view space normals mapped onto the texture, for environment maps
+    SphereMap,
+    // object space x/y stretched over the bounding box
+    Planar,
+    // wrapped around the object's y axis
+    Cylindrical,
+}
+
+// which faces are skipped, by their winding on screen
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CullMode {
+    // both sides drawn, for open meshes and double sided materials
+    None,
+    Back,
+    Front,
+}
+
+// how a pixel is combined with what's already in the color buffer
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    // overwrites, alpha ignored
+    Opaque,
+    // opaque, but pixels with alpha below the cutoff are dropped
+    AlphaTest,
+    // mixed by alpha
+    AlphaBlend,
+    // added on top, for glows and particles
+    Additive,
+    // darkens, for tinted glass and shadows
+    Multiply,
+}
+
+impl BlendMode {
+    // drawn after the opaque objects, without writing depth
+    pub fn is_transparent(self) -> bool {
+        match self {
+            BlendMode::Opaque | BlendMode::AlphaTest => false,
+            BlendMode::AlphaBlend | BlendMode::Additive | BlendMode::Multiply => true,
+        }
+    }
+}
+
+// destination alpha is left alone, the canvas stays opaque
+fn blend_pixel(mode: BlendMode, dst: &mut [u8], src: [u8; 3], alpha: f32) {
+    let alpha = alpha.clamp(0.0, 1.0);
+    for c in 0..3 {
+        let (s, d) = (src[c] as f32, dst[c] as f32);
+        let value = match mode {
+            BlendMode::Opaque | BlendMode::AlphaTest => s,
+            BlendMode::AlphaBlend => s * alpha + d * (1.0 - alpha),
+            BlendMode::Additive => d + s * alpha,
+            BlendMode::Multiply => d * (1.0 - alpha + s / 255.0 * alpha),
+        };
+        dst[c] = (value + 0.5).clamp(0.0, 255.0) as u8;
+    }
+    if !mode.is_transparent() {
+        dst[3] = 255;
+    }
+}
+
+// what a cube map environment shows on an object's surface
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvironmentMapping {
+    // the texture from the uv source
+    Off,
+    // the view vector mirrored about the normal
+    Reflection,
+    // the view vector bent into the surface by the refractive index
+    Refraction,
+}
+
+// how the camera maps view space onto the screen
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectionMode {
+    // symmetric around the view axis, from the field of vision
+    Perspective,
+    // parallel, a fixed height of the world fills the screen at any distance
+    Orthographic,
+    // perspective through an arbitrary window on the near plane
+    OffAxis,
+}
+
+// which controller turns pointer, wheel, touch and key input into camera motion
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraControl {
+    // the camera only moves through the set_camera_* calls
+    None,
+    // drag to turn around a target, wheel or pinch to zoom, right drag to pan
+    Orbit,
+    // drag to look, keys walk on the horizontal plane
+    FirstPerson,
+    // drag to look, keys move along the view and q / e roll
+    Fly,
+}
+
+// how normals are built for meshes that don't come with them
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalGeneration {
+    // one normal per face
+    Flat,
+    // shared across all faces meeting at a vertex
+    Smooth,
+    // shared within obj "s" groups, faces with smoothing off stay flat
+    SmoothingGroups,
+}
+
+// in clip space w is the homogeneous w, after projection to screen space
+// it holds 1/w so the rasterizer can do perspective correct interpolation
+#[derive(Debug, Clone)]
 pub struct VertexUV {
     pub x: f32,
     pub y: f32,
@@ -25,6 +182,20 @@ pub struct VertexUV {
     pub w: f32,
     pub u: f32,
     pub v: f32,
+    // lighting already evaluated for flat and gouraud shading
+    pub diffuse: math::Vector3,
+    pub specular: math::Vector3,
+    // vertex color, multiplied like the material color
+    pub color: math::Vector3,
+    // world space position and normal for phong shading
+    pub position: math::Vector3,
+    pub normal: math::Vector3,
+    // mesh uvs for the normal map, u/v above may come from a projection instead
+    pub mesh_u: f32,
+    pub mesh_v: f32,
+    // world space tangent frame for normal mapping
+    pub tangent: math::Vector3,
+    pub bitangent: math::Vector3,
 }
 
 impl VertexUV {
@@ -43,15 +214,205 @@ impl VertexUV {
             w: self.w * a + b.w * (1.0 - a),
             u: self.u * a + b.u * (1.0 - a),
             v: self.v * a + b.v * (1.0 - a),
+            diffuse: self.diffuse.lerp(&b.diffuse, a),
+            specular: self.specular.lerp(&b.specular, a),
+            color: self.color.lerp(&b.color, a),
+            position: self.position.lerp(&b.position, a),
+            normal: self.normal.lerp(&b.normal, a),
+            mesh_u: self.mesh_u * a + b.mesh_u * (1.0 - a),
+            mesh_v: self.mesh_v * a + b.mesh_v * (1.0 - a),
+            tangent: self.tangent.lerp(&b.tangent, a),
+            bitangent: self.bitangent.lerp(&b.bitangent, a),
+        }
+    }
+}
+
+// everything besides the vertices needed to rasterize a triangle
+#[derive(Clone, Copy)]
+pub struct DrawParams<'a> {
+    pub texture: &'a Texture,
+    // multiplied with the texture, the material diffuse color
+    pub color: math::Vector3,
+    pub interpolation: TextureInterpolation,
+    pub filter: TextureFilter,
+    pub shading: ShadingMode,
+    pub lights: &'a [scene::Light],
+    pub ambient: math::Vector3,
+    pub eye: math::Vector3,
+    pub specular: math::Vector3,
+    pub shininess: f32,
+    // tangent space normals, lighting becomes per pixel when set
+    pub normal_map: Option<&'a Texture>,
+    // view rotation when u/v come from the sphere map, so the
+    // environment lookup can follow the perturbed normal
+    pub sphere_map: Option<math::Matrix4>,
+    // replaces the texture when set
+    pub environment: Option<&'a cubemap::CubeMap>,
+    pub environment_mapping: EnvironmentMapping,
+    pub refractive_index: f32,
+    pub blend: BlendMode,
+    // alpha below this is dropped in AlphaTest mode
+    pub alpha_cutoff: f32,
+    // material or object opacity, multiplied with the texture alpha
+    pub opacity: f32,
+}
+
+// vertex attribute (premultiplied by 1/w) as a function of the edge weights
+#[derive(Clone, Copy)]
+struct Gradient {
+    base: f32,
+    d1: f32,
+    d2: f32,
+}
+
+impl Gradient {
+    fn new(a0: f32, a1: f32, a2: f32, r_area: f32) -> Gradient {
+        Gradient { base: a0, d1: (a1 - a0) * r_area, d2: (a2 - a0) * r_area }
+    }
+
+    fn at(&self, w1: f32, w2: f32) -> f32 {
+        self.base + self.d1 * w1 + self.d2 * w2
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Gradient3 {
+    x: Gradient,
+    y: Gradient,
+    z: Gradient,
+}
+
+impl Gradient3 {
+    fn new(a0: math::Vector3, a1: math::Vector3, a2: math::Vector3, r_area: f32) -> Gradient3 {
+        Gradient3 {
+            x: Gradient::new(a0.x, a1.x, a2.x, r_area),
+            y: Gradient::new(a0.y, a1.y, a2.y, r_area),
+            z: Gradient::new(a0.z, a1.z, a2.z, r_area),
+        }
+    }
+
+    fn at(&self, w1: f32, w2: f32, w: f32) -> math::Vector3 {
+        math::Vector3 {
+            x: self.x.at(w1, w2) * w,
+            y: self.y.at(w1, w2) * w,
+            z: self.z.at(w1, w2) * w,
+        }
+    }
+}
+
+fn shade_channel(texel: u8, color: f32, diffuse: f32, specular: f32) -> u8 {
+    (texel as f32 * color * diffuse + specular * 255.0).clamp(0.0, 255.0) as u8
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureFilter {
+    // single texel from the base level
+    Nearest,
+    // bilinear filtered texels from the closest mip level
+    Bilinear,
+    // bilinear filtered texels blended between two mip levels
+    Trilinear,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureWrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+// maps a texel coordinate into 0..size according to the wrap mode
+fn wrap_coord(wrap: TextureWrap, c: i32, size: u32) -> u32 {
+    let size = size as i32;
+    match wrap {
+        TextureWrap::Repeat => {
+            // power of two sizes can skip the division
+            if size & (size - 1) == 0 {
+                (c & (size - 1)) as u32
+            } else {
+                c.rem_euclid(size) as u32
+            }
+        },
+        TextureWrap::MirroredRepeat => {
+            let m = c.rem_euclid(size * 2);
+            if m >= size { (size * 2 - 1 - m) as u32 } else { m as u32 }
+        },
+        TextureWrap::ClampToEdge => c.max(0).min(size - 1) as u32,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextureError {
+    // zero sized, or too big to address
+    InvalidSize { width: u32, height: u32 },
+    // fewer bytes than width x height rgba texels
+    TooShort { expected: usize, found: usize },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureError::InvalidSize { width, height } => write!(f, "invalid texture size {}x{}", width, height),
+            TextureError::TooShort { expected, found } => write!(f, "expected {} bytes of texture data, found {}", expected, found),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Texture {
     pub width: u32,
     pub height: u32,
     pub data: Vec<u8>,
+    // downsampled levels, mips[0] is half the size of the base level
+    pub mips: Vec<MipLevel>,
+    pub wrap: TextureWrap,
+}
+
+// 2x2 box filter, odd edges reuse the last row / column
+fn downsample(width: u32, height: u32, data: &[u8]) -> MipLevel {
+    let mw = (width / 2).max(1);
+    let mh = (height / 2).max(1);
+    let mut mip_data = vec![0u8; (mw * mh * 4) as usize];
+
+    for y in 0..mh {
+        let y0 = (y * 2).min(height - 1);
+        let y1 = (y * 2 + 1).min(height - 1);
+        for x in 0..mw {
+            let x0 = (x * 2).min(width - 1);
+            let x1 = (x * 2 + 1).min(width - 1);
+
+            let i00 = ((y0 * width + x0) * 4) as usize;
+            let i01 = ((y0 * width + x1) * 4) as usize;
+            let i10 = ((y1 * width + x0) * 4) as usize;
+            let i11 = ((y1 * width + x1) * 4) as usize;
+            let o = ((y * mw + x) * 4) as usize;
+
+            for c in 0..4 {
+                let sum = data[i00 + c] as u32 + data[i01 + c] as u32 + data[i10 + c] as u32 + data[i11 + c] as u32;
+                mip_data[o + c] = ((sum + 2) / 4) as u8;
+            }
+        }
+    }
+
+    MipLevel { width: mw, height: mh, data: mip_data }
+}
+
+fn lerp_texel(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+        a[3] + (b[3] - a[3]) * t,
+    ]
 }
 
 impl Texture {
@@ -60,6 +421,125 @@ impl Texture {
             width: 0,
             height: 0,
             data: vec![],
+            mips: vec![],
+            wrap: TextureWrap::Repeat,
+        }
+    }
+
+    // 1x1 white, for surfaces colored only by material or vertex colors
+    pub fn blank() -> Texture {
+        Texture::from_rgba(vec![255; 4], 1, 1)
+    }
+
+    // takes RGBA8 data and builds the full mip chain down to 1x1
+    pub fn from_rgba(data: Vec<u8>, width: u32, height: u32) -> Texture {
+        let mut texture = Texture { width, height, data, mips: vec![], wrap: TextureWrap::Repeat };
+        texture.build_mips();
+        texture
+    }
+
+    // from_rgba for sizes and data that haven't been checked, like those from js
+    pub fn try_from_rgba(data: Vec<u8>, width: u32, height: u32) -> Result<Texture, TextureError> {
+        let expected = (width as usize).checked_mul(height as usize).and_then(|texels| texels.checked_mul(4))
+            .filter(|&expected| expected > 0 && width <= i32::MAX as u32 && height <= i32::MAX as u32)
+            .ok_or(TextureError::InvalidSize { width, height })?;
+        if data.len() < expected {
+            return Err(TextureError::TooShort { expected, found: data.len() });
+        }
+        Ok(Texture::from_rgba(data, width, height))
+    }
+
+    pub fn build_mips(&mut self) {
+        self.mips.clear();
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        let mut mip = downsample(self.width, self.height, &self.data);
+        loop {
+            let done = mip.width == 1 && mip.height == 1;
+            let next = if done { None } else { Some(downsample(mip.width, mip.height, &mip.data)) };
+            self.mips.push(mip);
+            match next {
+                Some(m) => mip = m,
+                None => break,
+            }
+        }
+    }
+
+    fn level(&self, level: usize) -> (u32, u32, &[u8]) {
+        if level == 0 {
+            (self.width, self.height, &self.data)
+        } else {
+            let mip = &self.mips[level - 1];
+            (mip.width, mip.height, &mip.data)
+        }
+    }
+
+    fn texel(&self, level: usize, x: i32, y: i32) -> [f32; 4] {
+        let (width, height, data) = self.level(level);
+        // Texture::new() and anything built with a zero size draw like blank()
+        if width == 0 || height == 0 {
+            return [255.0; 4];
+        }
+        let tx = wrap_coord(self.wrap, x, width);
+        let ty = wrap_coord(self.wrap, y, height);
+        let i = ((ty * width + tx) * 4) as usize;
+        [data[i] as f32, data[i + 1] as f32, data[i + 2] as f32, data[i + 3] as f32]
+    }
+
+    fn sample_bilinear(&self, level: usize, u: f32, v: f32) -> [f32; 4] {
+        let (width, height, _) = self.level(level);
+        let x = u * width as f32 - 0.5;
+        let y = v * height as f32 - 0.5;
+        let fx = x.floor();
+        let fy = y.floor();
+        let tx = x - fx;
+        let ty = y - fy;
+        let x0 = fx as i32;
+        let y0 = fy as i32;
+
+        let top = lerp_texel(self.texel(level, x0, y0), self.texel(level, x0 + 1, y0), tx);
+        let bottom = lerp_texel(self.texel(level, x0, y0 + 1), self.texel(level, x0 + 1, y0 + 1), tx);
+        lerp_texel(top, bottom, ty)
+    }
+
+    // level of detail from screen space derivatives of normalized u/v
+    pub fn lod(&self, dudx: f32, dvdx: f32, dudy: f32, dvdy: f32) -> f32 {
+        let w = self.width as f32;
+        let h = self.height as f32;
+        let dx = (dudx * w) * (dudx * w) + (dvdx * h) * (dvdx * h);
+        let dy = (dudy * w) * (dudy * w) + (dvdy * h) * (dvdy * h);
+        (0.5 * dx.max(dy).log2()).max(0.0)
+    }
+
+    pub fn sample(&self, filter: TextureFilter, u: f32, v: f32, lod: f32) -> Color {
+        let max_level = self.mips.len();
+        let texel = match filter {
+            TextureFilter::Nearest => {
+                self.texel(0, (u * self.width as f32).floor() as i32, (v * self.height as f32).floor() as i32)
+            },
+            TextureFilter::Bilinear => {
+                let level = ((lod + 0.5) as usize).min(max_level);
+                self.sample_bilinear(level, u, v)
+            },
+            TextureFilter::Trilinear => {
+                let level = (lod as usize).min(max_level);
+                let t = if level == max_level { 0.0 } else { lod - level as f32 };
+                let a = self.sample_bilinear(level, u, v);
+                if t > 0.0 {
+                    lerp_texel(a, self.sample_bilinear(level + 1, u, v), t)
+                } else {
+                    a
+                }
+            },
+        };
+
+        Color {
+            r: (texel[0] + 0.5) as u8,
+            g: (texel[1] + 0.5) as u8,
+            b: (texel[2] + 0.5) as u8,
+            a: (texel[3] + 0.5) as u8,
         }
     }
 }
@@ -332,7 +812,10 @@ pub fn draw_triangle_barycentric_z(target: &mut RenderTarget, c: &Color, p0: mat
     }
 }
 
-pub fn draw_triangle_barycentric_z_uv(target: &mut RenderTarget, texture: &Texture, p0: &VertexUV, p1: &VertexUV, p2: &VertexUV) {
+pub fn draw_triangle_barycentric_z_uv(target: &mut RenderTarget, params: &DrawParams, p0: &VertexUV, p1: &VertexUV, p2: &VertexUV) {
+    let texture = params.texture;
+    let filter = params.filter;
+
     let v0 = p2.to_screen_point();
     let v1 = p1.to_screen_point();
     let v2 = p0.to_screen_point();
@@ -341,12 +824,11 @@ pub fn draw_triangle_barycentric_z_uv(target: &mut RenderTarget, texture: &Textu
     let z1 = p1.z;
     let z2 = p0.z;
 
-    let tu0 = p2.u;
-    let tv0 = p2.v;
-    let tu1 = p1.u;
-    let tv1 = p1.v;
-    let tu2 = p0.u;
-    let tv2 = p0.v;
+    // affine mapping is the same as perspective correct with constant w
+    let (rw0, rw1, rw2) = match params.interpolation {
+        TextureInterpolation::PerspectiveCorrect => (p2.w, p1.w, p0.w),
+        TextureInterpolation::Affine => (1.0, 1.0, 1.0),
+    };
 
     let minx = (v0.x.min(v1.x.min(v2.x)) as i32).max(0);
     let miny = (v0.y.min(v1.y.min(v2.y)) as i32).max(0);
@@ -379,10 +861,19 @@ pub fn draw_triangle_barycentric_z_uv(target: &mut RenderTarget, texture: &Textu
     let zz1 = (z1 - z0) * r_area;
     let zz2 = (z2 - z0) * r_area;
 
-    let ttu1 = (tu1 - tu0) * r_area;
-    let ttv1 = (tv1 - tv0) * r_area;
-    let ttu2 = (tu2 - tu0) * r_area;
-    let ttv2 = (tv2 - tv0) * r_area;
+    let gw = Gradient::new(rw0, rw1, rw2, r_area);
+    let gu = Gradient::new(p2.u * rw0, p1.u * rw1, p0.u * rw2, r_area);
+    let gv = Gradient::new(p2.v * rw0, p1.v * rw1, p0.v * rw2, r_area);
+
+    let gdiffuse = Gradient3::new(p2.diffuse.scale(rw0), p1.diffuse.scale(rw1), p0.diffuse.scale(rw2), r_area);
+    let gspecular = Gradient3::new(p2.specular.scale(rw0), p1.specular.scale(rw1), p0.specular.scale(rw2), r_area);
+    let gcolor = Gradient3::new(p2.color.scale(rw0), p1.color.scale(rw1), p0.color.scale(rw2), r_area);
+    let gposition = Gradient3::new(p2.position.scale(rw0), p1.position.scale(rw1), p0.position.scale(rw2), r_area);
+    let gnormal = Gradient3::new(p2.normal.scale(rw0), p1.normal.scale(rw1), p0.normal.scale(rw2), r_area);
+    let gmesh_u = Gradient::new(p2.mesh_u * rw0, p1.mesh_u * rw1, p0.mesh_u * rw2, r_area);
+    let gmesh_v = Gradient::new(p2.mesh_v * rw0, p1.mesh_v * rw1, p0.mesh_v * rw2, r_area);
+    let gtangent = Gradient3::new(p2.tangent.scale(rw0), p1.tangent.scale(rw1), p0.tangent.scale(rw2), r_area);
+    let gbitangent = Gradient3::new(p2.bitangent.scale(rw0), p1.bitangent.scale(rw1), p0.bitangent.scale(rw2), r_area);
 
     for _y in miny..maxy {
         let mut w0 = w0_row;
@@ -402,15 +893,98 @@ pub fn draw_triangle_barycentric_z_uv(target: &mut RenderTarget, texture: &Textu
             if w0 >= 0 && w1 >= 0 && w2 >= 0 {
                 let z = z0 + zz1 * w1 as f32 + zz2 * w2 as f32;
 
-                let u = ((tu0 + ttu1 * w1 as f32 + ttu2 * w2 as f32) * texture.width as f32) as u32 & (texture.width - 1);
-                let v = ((tv0 + ttv1 * w1 as f32 + ttv2 * w2 as f32) * texture.height as f32) as u32 & (texture.height - 1);
-
                 if *depth_buffer > z {
-                    x[0] = texture.data[((v * texture.width) + u) as usize * 4 + 0];
-                    x[1] = texture.data[((v * texture.width) + u) as usize * 4 + 1];
-                    x[2] = texture.data[((v * texture.width) + u) as usize * 4 + 2];
-                    x[3] = 255;
-                    *depth_buffer = z;
+                    let fw1 = w1 as f32;
+                    let fw2 = w2 as f32;
+                    let rw = gw.at(fw1, fw2);
+                    let w = 1.0 / rw;
+                    let su = gu.at(fw1, fw2);
+                    let sv = gv.at(fw1, fw2);
+                    let u = su * w;
+                    let v = sv * w;
+
+                    let lod = if filter == TextureFilter::Nearest {
+                        0.0
+                    } else {
+                        // u/v one pixel to the right and one pixel down
+                        let (ax, ay) = (a1 as f32, a2 as f32);
+                        let (bx, by) = (b1 as f32, b2 as f32);
+                        let rwx = rw + gw.d1 * ax + gw.d2 * ay;
+                        let rwy = rw + gw.d1 * bx + gw.d2 * by;
+                        let dudx = (su + gu.d1 * ax + gu.d2 * ay) / rwx - u;
+                        let dvdx = (sv + gv.d1 * ax + gv.d2 * ay) / rwx - v;
+                        let dudy = (su + gu.d1 * bx + gu.d2 * by) / rwy - u;
+                        let dvdy = (sv + gv.d1 * bx + gv.d2 * by) / rwy - v;
+                        texture.lod(dudx, dvdx, dudy, dvdy)
+                    };
+
+                    // world space normal perturbed by the normal map
+                    let mapped_normal = params.normal_map.map(|normal_map| {
+                        let n = gnormal.at(fw1, fw2, w).normal();
+                        let t = gtangent.at(fw1, fw2, w);
+                        let b = gbitangent.at(fw1, fw2, w);
+                        let m = normal_map.sample(TextureFilter::Bilinear, gmesh_u.at(fw1, fw2) * w, gmesh_v.at(fw1, fw2) * w, 0.0);
+                        let channel = |c: u8| c as f32 / 127.5 - 1.0;
+                        t.scale(channel(m.r)).add(&b.scale(channel(m.g))).add(&n.scale(channel(m.b))).normal()
+                    });
+
+                    let (u, v) = match (mapped_normal, params.sphere_map) {
+                        (Some(n), Some(view_rotation)) => {
+                            let uv = scene::sphere_map_uv(&math::Vector4 { x: n.x, y: n.y, z: n.z, w: 0.0 }.multiply(&view_rotation));
+                            (uv.x, uv.y)
+                        },
+                        _ => (u, v),
+                    };
+
+                    let texel = match params.environment {
+                        Some(cube_map) => {
+                            let normal = mapped_normal.unwrap_or_else(|| gnormal.at(fw1, fw2, w).normal());
+                            let incident = gposition.at(fw1, fw2, w).sub(&params.eye).normal();
+                            let direction = match params.environment_mapping {
+                                EnvironmentMapping::Refraction => cubemap::refract(&incident, &normal, 1.0 / params.refractive_index),
+                                _ => cubemap::reflect(&incident, &normal),
+                            };
+                            cube_map.sample(filter, &direction)
+                        },
+                        None => texture.sample(filter, u, v, lod),
+                    };
+                    let alpha = texel.a as f32 / 255.0 * params.opacity;
+                    if params.blend == BlendMode::AlphaTest && alpha < params.alpha_cutoff {
+                        w0 += a0;
+                        w1 += a1;
+                        w2 += a2;
+                        continue;
+                    }
+
+                    let color = params.color.mul(&gcolor.at(fw1, fw2, w));
+                    let rgb = match params.shading {
+                        ShadingMode::Unlit => [
+                            shade_channel(texel.r, color.x, 1.0, 0.0),
+                            shade_channel(texel.g, color.y, 1.0, 0.0),
+                            shade_channel(texel.b, color.z, 1.0, 0.0),
+                        ],
+                        ShadingMode::Flat | ShadingMode::Gouraud | ShadingMode::Phong => {
+                            // normal mapped surfaces are always lit per pixel
+                            let (diffuse, specular) = if params.shading == ShadingMode::Phong || mapped_normal.is_some() {
+                                let position = gposition.at(fw1, fw2, w);
+                                let normal = mapped_normal.unwrap_or_else(|| gnormal.at(fw1, fw2, w).normal());
+                                scene::shade(params.lights, &params.ambient, &position, &normal, &params.eye, &params.specular, params.shininess)
+                            } else {
+                                (gdiffuse.at(fw1, fw2, w), gspecular.at(fw1, fw2, w))
+                            };
+                            [
+                                shade_channel(texel.r, color.x, diffuse.x, specular.x),
+                                shade_channel(texel.g, color.y, diffuse.y, specular.y),
+                                shade_channel(texel.b, color.z, diffuse.z, specular.z),
+                            ]
+                        },
+                    };
+
+                    blend_pixel(params.blend, x, rgb, alpha);
+                    // transparent surfaces don't hide what's drawn after them
+                    if !params.blend.is_transparent() {
+                        *depth_buffer = z;
+                    }
                 }
             }
             w0 += a0;
@@ -423,3 +997,118 @@ pub fn draw_triangle_barycentric_z_uv(target: &mut RenderTarget, texture: &Textu
         w2_row += b2;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_texture_mip_chain() {
+        let texture = Texture::from_rgba(vec![255; 8 * 4 * 4], 8, 4);
+        assert_eq!(texture.mips.len(), 3);
+        assert_eq!((texture.mips[0].width, texture.mips[0].height), (4, 2));
+        assert_eq!((texture.mips[1].width, texture.mips[1].height), (2, 1));
+        assert_eq!((texture.mips[2].width, texture.mips[2].height), (1, 1));
+    }
+
+    #[test]
+    fn test_texture_try_from_rgba() {
+        assert_eq!(Texture::try_from_rgba(vec![255; 16], 2, 2).unwrap().mips.len(), 1);
+        assert_eq!(Texture::try_from_rgba(vec![255; 15], 2, 2).err(), Some(TextureError::TooShort { expected: 16, found: 15 }));
+        assert_eq!(Texture::try_from_rgba(vec![], 0, 4).err(), Some(TextureError::InvalidSize { width: 0, height: 4 }));
+        // texel coordinates are i32
+        assert_eq!(Texture::try_from_rgba(vec![255; 4], u32::MAX, 1).err(), Some(TextureError::InvalidSize { width: u32::MAX, height: 1 }));
+    }
+
+    #[test]
+    fn test_texture_mip_average() {
+        let data = vec![
+            0, 0, 0, 255,     100, 0, 0, 255,
+            0, 200, 0, 255,   0, 0, 40, 255,
+        ];
+        let texture = Texture::from_rgba(data, 2, 2);
+        assert_eq!(texture.mips[0].data, vec![25, 50, 10, 255]);
+    }
+
+    #[test]
+    fn test_texture_sample_bilinear() {
+        let data = vec![
+            0, 0, 0, 255,     200, 0, 0, 255,
+            0, 0, 0, 255,     200, 0, 0, 255,
+        ];
+        let texture = Texture::from_rgba(data, 2, 2);
+
+        let nearest = texture.sample(TextureFilter::Nearest, 0.5, 0.5, 0.0);
+        assert_eq!(nearest.r, 200);
+
+        let bilinear = texture.sample(TextureFilter::Bilinear, 0.5, 0.25, 0.0);
+        assert_eq!(bilinear.r, 100);
+    }
+
+    #[test]
+    fn test_blend_pixel() {
+        let blend = |mode, alpha| {
+            let mut dst = [100, 200, 0, 255];
+            blend_pixel(mode, &mut dst, [200, 100, 255], alpha);
+            dst
+        };
+        assert_eq!(blend(BlendMode::Opaque, 0.0), [200, 100, 255, 255]);
+        assert_eq!(blend(BlendMode::AlphaBlend, 0.5), [150, 150, 128, 255]);
+        assert_eq!(blend(BlendMode::Additive, 1.0), [255, 255, 255, 255]);
+        assert_eq!(blend(BlendMode::Multiply, 1.0), [78, 78, 0, 255]);
+        assert_eq!(blend(BlendMode::Multiply, 0.0), [100, 200, 0, 255]);
+    }
+
+    #[test]
+    fn test_wrap_coord() {
+        assert_eq!(wrap_coord(TextureWrap::Repeat, 5, 4), 1);
+        assert_eq!(wrap_coord(TextureWrap::Repeat, -1, 4), 3);
+        assert_eq!(wrap_coord(TextureWrap::Repeat, 7, 5), 2);
+        assert_eq!(wrap_coord(TextureWrap::Repeat, -1, 5), 4);
+
+        assert_eq!(wrap_coord(TextureWrap::MirroredRepeat, 2, 3), 2);
+        assert_eq!(wrap_coord(TextureWrap::MirroredRepeat, 3, 3), 2);
+        assert_eq!(wrap_coord(TextureWrap::MirroredRepeat, 5, 3), 0);
+        assert_eq!(wrap_coord(TextureWrap::MirroredRepeat, -1, 3), 0);
+        assert_eq!(wrap_coord(TextureWrap::MirroredRepeat, -4, 3), 2);
+
+        assert_eq!(wrap_coord(TextureWrap::ClampToEdge, -3, 3), 0);
+        assert_eq!(wrap_coord(TextureWrap::ClampToEdge, 1, 3), 1);
+        assert_eq!(wrap_coord(TextureWrap::ClampToEdge, 9, 3), 2);
+    }
+
+    #[test]
+    fn test_texture_npot_sample() {
+        let data = vec![
+            10, 0, 0, 255,   20, 0, 0, 255,   30, 0, 0, 255,
+        ];
+        let mut texture = Texture::from_rgba(data, 3, 1);
+        assert_eq!(texture.mips[0].width, 1);
+
+        assert_eq!(texture.sample(TextureFilter::Nearest, 1.2, 0.0, 0.0).r, 10);
+        texture.wrap = TextureWrap::ClampToEdge;
+        assert_eq!(texture.sample(TextureFilter::Nearest, 1.2, 0.0, 0.0).r, 30);
+        texture.wrap = TextureWrap::MirroredRepeat;
+        assert_eq!(texture.sample(TextureFilter::Nearest, 1.2, 0.0, 0.0).r, 30);
+        assert_eq!(texture.sample(TextureFilter::Nearest, 1.9, 0.0, 0.0).r, 10);
+    }
+
+    #[test]
+    fn test_texture_zero_size_sample() {
+        for &wrap in [TextureWrap::Repeat, TextureWrap::MirroredRepeat, TextureWrap::ClampToEdge].iter() {
+            let mut texture = Texture::new();
+            texture.wrap = wrap;
+            for &filter in [TextureFilter::Nearest, TextureFilter::Bilinear, TextureFilter::Trilinear].iter() {
+                let c = texture.sample(filter, 0.3, 0.7, 1.5);
+                assert_eq!((c.r, c.g, c.b, c.a), (255, 255, 255, 255));
+            }
+        }
+    }
+
+    #[test]
+    fn test_texture_lod() {
+        let texture = Texture::from_rgba(vec![0; 64 * 64 * 4], 64, 64);
+        assert_eq!(texture.lod(1.0 / 64.0, 0.0, 0.0, 1.0 / 64.0), 0.0);
+        assert_eq!(texture.lod(4.0 / 64.0, 0.0, 0.0, 1.0 / 64.0), 2.0);
+    }
+}
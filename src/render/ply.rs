@@ -0,0 +1,388 @@
+use std::fmt;
+use crate::math;
+use super::scene::{Face, Object};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlyError {
+    InvalidHeader(String),
+    Unsupported(String),
+    // the body ended before all elements were read
+    Truncated { element: String, index: usize },
+    InvalidValue { element: String, index: usize, reason: String },
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlyError::InvalidHeader(reason) => write!(f, "invalid ply header: {}", reason),
+            PlyError::Unsupported(what) => write!(f, "unsupported {}", what),
+            PlyError::Truncated { element, index } => write!(f, "file ends in {} {}", element, index),
+            PlyError::InvalidValue { element, index, reason } => write!(f, "{} {}: {}", element, index, reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<ScalarType> {
+        match name {
+            "char" | "int8" => Some(ScalarType::I8),
+            "uchar" | "uint8" => Some(ScalarType::U8),
+            "short" | "int16" => Some(ScalarType::I16),
+            "ushort" | "uint16" => Some(ScalarType::U16),
+            "int" | "int32" => Some(ScalarType::I32),
+            "uint" | "uint32" => Some(ScalarType::U32),
+            "float" | "float32" => Some(ScalarType::F32),
+            "double" | "float64" => Some(ScalarType::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, ScalarType::F32 | ScalarType::F64)
+    }
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: ScalarType,
+    // type of the length prefix for list properties
+    list_count: Option<ScalarType>,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// reads values one after another from the body in either encoding
+struct BodyReader<'a> {
+    format: Format,
+    data: &'a [u8],
+    position: usize,
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> BodyReader<'a> {
+    fn new(format: Format, data: &'a [u8]) -> Result<BodyReader<'a>, PlyError> {
+        let text = if format == Format::Ascii {
+            std::str::from_utf8(data).map_err(|_| PlyError::Unsupported("non utf-8 ascii body".to_string()))?
+        } else {
+            ""
+        };
+        Ok(BodyReader { format, data, position: 0, tokens: text.split_ascii_whitespace() })
+    }
+
+    // None at the end of the data, Err(token) for ascii values that aren't numbers
+    fn read(&mut self, kind: ScalarType) -> Option<Result<f64, String>> {
+        if self.format == Format::Ascii {
+            let token = self.tokens.next()?;
+            return Some(token.parse::<f64>().map_err(|_| format!("invalid number '{}'", token)));
+        }
+
+        let size = kind.size();
+        let bytes = self.data.get(self.position..self.position + size)?;
+        self.position += size;
+        let mut b = [0u8; 8];
+        b[..size].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            b[..size].reverse();
+        }
+        let value = match kind {
+            ScalarType::I8 => b[0] as i8 as f64,
+            ScalarType::U8 => b[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(b),
+        };
+        Some(Ok(value))
+    }
+}
+
+pub struct PlyLoader {
+
+}
+
+impl PlyLoader {
+    pub fn is_ply(data: &[u8]) -> bool {
+        data.starts_with(b"ply\n") || data.starts_with(b"ply\r\n")
+    }
+
+    // returns the format, the elements and where the body starts
+    fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), PlyError> {
+        if !PlyLoader::is_ply(data) {
+            return Err(PlyError::InvalidHeader("missing 'ply' magic".to_string()));
+        }
+
+        let mut format = None;
+        let mut elements: Vec<Element> = vec![];
+        let mut offset = 0;
+
+        // the header is ascii, one statement per line
+        while offset < data.len() {
+            let end = data[offset..].iter().position(|&b| b == b'\n').map(|p| offset + p + 1).unwrap_or(data.len());
+            let line = String::from_utf8_lossy(&data[offset..end]);
+            offset = end;
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.is_empty() {
+                continue;
+            }
+
+            match parts[0] {
+                "format" => {
+                    format = match parts.get(1) {
+                        Some(&"ascii") => Some(Format::Ascii),
+                        Some(&"binary_little_endian") => Some(Format::BinaryLittleEndian),
+                        Some(&"binary_big_endian") => Some(Format::BinaryBigEndian),
+                        other => return Err(PlyError::Unsupported(format!("format {:?}", other))),
+                    };
+                },
+                "element" => {
+                    if parts.len() < 3 {
+                        return Err(PlyError::InvalidHeader(line.trim().to_string()));
+                    }
+                    let count = parts[2].parse::<usize>().map_err(|_| PlyError::InvalidHeader(line.trim().to_string()))?;
+                    elements.push(Element { name: parts[1].to_string(), count, properties: vec![] });
+                },
+                "property" => {
+                    let element = elements.last_mut().ok_or_else(|| PlyError::InvalidHeader("property before element".to_string()))?;
+                    let property = if parts.get(1) == Some(&"list") && parts.len() >= 5 {
+                        Property {
+                            name: parts[4].to_string(),
+                            kind: ScalarType::parse(parts[3]).ok_or_else(|| PlyError::InvalidHeader(line.trim().to_string()))?,
+                            list_count: Some(ScalarType::parse(parts[2]).ok_or_else(|| PlyError::InvalidHeader(line.trim().to_string()))?),
+                        }
+                    } else if parts.len() >= 3 {
+                        Property {
+                            name: parts[2].to_string(),
+                            kind: ScalarType::parse(parts[1]).ok_or_else(|| PlyError::InvalidHeader(line.trim().to_string()))?,
+                            list_count: None,
+                        }
+                    } else {
+                        return Err(PlyError::InvalidHeader(line.trim().to_string()));
+                    };
+                    element.properties.push(property);
+                },
+                "end_header" => {
+                    let format = format.ok_or_else(|| PlyError::InvalidHeader("missing format".to_string()))?;
+                    return Ok((format, elements, offset));
+                },
+                _ => {
+
+                }
+            }
+        }
+        Err(PlyError::InvalidHeader("missing end_header".to_string()))
+    }
+
+    pub fn load_ply(data: &[u8]) -> Result<Object, PlyError> {
+        let (format, elements, body) = PlyLoader::parse_header(data)?;
+        let mut reader = BodyReader::new(format, &data[body..])?;
+        let mut obj = Object::new();
+        let mut normals: Vec<math::Vector4> = vec![];
+        let mut polygons: Vec<Vec<i32>> = vec![];
+
+        for element in elements.iter() {
+            let column = |name: &str| element.properties.iter().position(|p| p.name == name);
+            let (x, y, z) = (column("x"), column("y"), column("z"));
+            let normal = (column("nx"), column("ny"), column("nz"));
+            let color = (
+                column("red").or_else(|| column("diffuse_red")),
+                column("green").or_else(|| column("diffuse_green")),
+                column("blue").or_else(|| column("diffuse_blue")),
+            );
+            let uv = (
+                column("s").or_else(|| column("u")).or_else(|| column("texture_u")),
+                column("t").or_else(|| column("v")).or_else(|| column("texture_v")),
+            );
+            let indices = column("vertex_indices").or_else(|| column("vertex_index"));
+
+            for index in 0..element.count {
+                // every property has to be read to get to the next one, even unused ones
+                let mut values: Vec<Vec<f64>> = Vec::with_capacity(element.properties.len());
+                for property in element.properties.iter() {
+                    let mut read = |kind: ScalarType| match reader.read(kind) {
+                        Some(Ok(value)) => Ok(value),
+                        Some(Err(reason)) => Err(PlyError::InvalidValue { element: element.name.clone(), index, reason }),
+                        None => Err(PlyError::Truncated { element: element.name.clone(), index }),
+                    };
+                    let value = match property.list_count {
+                        Some(count_kind) => {
+                            let count = read(count_kind)? as usize;
+                            (0..count).map(|_| read(property.kind)).collect::<Result<Vec<f64>, PlyError>>()?
+                        },
+                        None => vec![read(property.kind)?],
+                    };
+                    values.push(value);
+                }
+                let scalar = |i: usize| values[i].first().cloned().unwrap_or(0.0) as f32;
+
+                if element.name == "vertex" {
+                    if let (Some(x), Some(y), Some(z)) = (x, y, z) {
+                        obj.vertices.push(math::Vector4 { x: scalar(x), y: scalar(y), z: scalar(z), w: 1.0 });
+                    }
+                    if let (Some(nx), Some(ny), Some(nz)) = normal {
+                        normals.push(math::Vector4 { x: scalar(nx), y: scalar(ny), z: scalar(nz), w: 0.0 });
+                    }
+                    if let (Some(r), Some(g), Some(b)) = color {
+                        // integer colors are 0..255, floating point ones 0..1
+                        let channel = |i: usize| if element.properties[i].kind.is_integer() { scalar(i) / 255.0 } else { scalar(i) };
+                        obj.colors.push(math::Vector3 { x: channel(r), y: channel(g), z: channel(b) });
+                    }
+                    if let (Some(u), Some(v)) = uv {
+                        obj.uvs.push(math::Point { x: scalar(u), y: scalar(v) });
+                    }
+                } else if element.name == "face" {
+                    if let Some(i) = indices {
+                        polygons.push(values[i].iter().map(|&v| v as i32).collect());
+                    }
+                }
+            }
+        }
+
+        let count = obj.vertices.len();
+        let has_uvs = obj.uvs.len() == count;
+        for (index, polygon) in polygons.iter().enumerate() {
+            if let Some(&bad) = polygon.iter().find(|&&v| v < 0 || v as usize >= count) {
+                return Err(PlyError::InvalidValue { element: "face".to_string(), index, reason: format!("vertex index {} out of range", bad) });
+            }
+            if polygon.len() < 3 {
+                continue;
+            }
+            let positions: Vec<math::Vector4> = polygon.iter().map(|&v| obj.vertices[v as usize]).collect();
+            for [a, b, c] in super::scene::triangulate_polygon(&positions) {
+                let (v0, v1, v2) = (polygon[c], polygon[b], polygon[a]);
+                let uv = |v: i32| if has_uvs { v } else { -1 };
+                // same reversed winding as ObjLoader
+                obj.faces.push(Face { v0, v1, v2, vn0: -1, vn1: -1, vn2: -1, uv0: uv(v0), uv1: uv(v1), uv2: uv(v2), material: None, smoothing_group: 0 });
+            }
+        }
+
+        if normals.len() == count && count > 0 {
+            obj.vertex_normals = normals;
+            for face in obj.faces.iter_mut() {
+                face.vn0 = face.v0;
+                face.vn1 = face.v1;
+                face.vn2 = face.v2;
+            }
+        }
+        if obj.colors.len() != count {
+            obj.colors.clear();
+        }
+        if !has_uvs {
+            obj.uvs.clear();
+        }
+        obj.texture = super::Texture::blank();
+        Ok(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_ascii_ply() {
+        let ply = "ply
+format ascii 1.0
+comment a colored quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+        let obj = PlyLoader::load_ply(ply.as_bytes()).unwrap();
+        assert_eq!(obj.vertices.len(), 4);
+        assert_eq!(obj.faces.len(), 2);
+        assert_eq!(obj.colors[1], math::Vector3 { x: 0.0, y: 1.0, z: 0.0 });
+        // no normals in the file, they are left to the renderer
+        assert!(obj.vertex_normals.is_empty());
+        assert!(obj.faces.iter().all(|f| f.vn0 == -1 && f.vn1 == -1 && f.vn2 == -1));
+    }
+
+    #[test]
+    fn test_load_binary_ply() {
+        let mut data = b"ply
+format binary_big_endian 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+element face 1
+property list uchar ushort vertex_indices
+end_header
+".to_vec();
+        let vertices = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        for v in vertices.iter() {
+            for f in v.iter().chain([0.0f32, 0.0, 1.0].iter()) {
+                data.extend_from_slice(&f.to_be_bytes());
+            }
+        }
+        data.push(3);
+        for i in 0..3u16 {
+            data.extend_from_slice(&i.to_be_bytes());
+        }
+
+        let obj = PlyLoader::load_ply(&data).unwrap();
+        assert_eq!(obj.vertices[2], math::Vector4 { x: 0.0, y: 1.0, z: 0.0, w: 1.0 });
+        assert_eq!(obj.vertex_normals[0], math::Vector4 { x: 0.0, y: 0.0, z: 1.0, w: 0.0 });
+        assert_eq!((obj.faces[0].v0, obj.faces[0].v1, obj.faces[0].v2), (2, 1, 0));
+        assert!(obj.colors.is_empty());
+
+        let truncated = &data[..data.len() - 1];
+        assert_eq!(PlyLoader::load_ply(truncated).err(), Some(PlyError::Truncated { element: "face".to_string(), index: 0 }));
+    }
+
+    #[test]
+    fn test_rejects_bad_header() {
+        assert!(matches!(PlyLoader::load_ply(b"ply\nelement vertex 1\nend_header\n"), Err(PlyError::InvalidHeader(_))));
+        assert!(matches!(PlyLoader::load_ply(b"ply\nformat ascii 1.0\nelement vertex 1\n"), Err(PlyError::InvalidHeader(_))));
+    }
+}
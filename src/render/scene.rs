@@ -1,17 +1,104 @@
+use std::collections::HashMap;
+use std::mem;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+use wasm_bindgen::prelude::*;
 use crate::math;
+use super::material::Material;
 
 #[derive(Debug)]
 pub struct Camera {
     pub position: math::Vector3,
-    pub target: math::Vector3,
+    // turns the camera's own axes, x right, y up and z forward, into world space
+    pub orientation: math::Quaternion,
+    // the world direction look_at keeps at the top of the screen
+    pub up: math::Vector3,
+    pub projection: super::ProjectionMode,
+    // vertical, in degrees. the horizontal one follows the render target
     pub field_of_vision: f32,
-    pub aspect_ratio: f32,
+    // height of the view volume for orthographic projection, in world units
+    pub orthographic_height: f32,
+    // left, right, bottom, top of the off axis window on the near plane
+    pub window: [f32; 4],
+    pub near: f32,
+    pub far: f32,
+}
+
+// buffers draw keeps between frames, so drawing an unchanged scene
+// doesn't allocate once they have grown to size
+#[derive(Debug, Default)]
+pub struct DrawScratch {
+    world_cache: Vec<Option<math::Matrix4>>,
+    world_matrices: Vec<math::Matrix4>,
+    order: Vec<usize>,
+    transparent: Vec<(usize, f32)>,
+    clip_planes: Vec<super::clip::ClipPlane>,
+    transformed_vertices: Vec<math::Vector4>,
+    world_vertices: Vec<math::Vector4>,
+    world_normals: Vec<math::Vector4>,
+    view_normals: Vec<math::Vector4>,
+    polygon: Vec<super::VertexUV>,
+    clip_output: Vec<super::VertexUV>,
+}
+
+// counts from the last draw
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameStats {
+    pub objects_drawn: u32,
+    // visible objects entirely outside the view frustum
+    pub objects_culled: u32,
+    // triangles sent to the rasterizer, after culling and clipping
+    pub triangles_drawn: u32,
+}
+
+// what fills the pixels no object covers, colors in 0..1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    Solid(math::Vector3),
+    // top row to bottom row of the screen
+    Gradient { top: math::Vector3, bottom: math::Vector3 },
+    // one of the scene's cube maps, seen from the camera
+    Skybox(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+#[derive(Debug, Clone)]
+pub struct Light {
+    pub kind: LightKind,
+    // world space, unused for directional lights
+    pub position: math::Vector3,
+    // direction the light travels, unused for point lights
+    pub direction: math::Vector3,
+    pub color: math::Vector3,
+    pub intensity: f32,
+    // distance where point and spot lights fade out, 0.0 for no falloff
+    pub range: f32,
+    // spot cone half angles in radians, full intensity inside inner_angle
+    pub inner_angle: f32,
+    pub outer_angle: f32,
 }
 
 #[derive(Debug)]
 pub struct Scene {
-    pub objects: Vec<Object>,
+    pub objects: ObjectStore,
     pub camera: Camera,
+    pub interpolation: super::TextureInterpolation,
+    pub lights: Vec<Light>,
+    pub ambient: math::Vector3,
+    // stands in for materials without a diffuse map
+    pub blank_texture: super::Texture,
+    // environments objects can reflect, addressed by index
+    pub cube_maps: Vec<super::cubemap::CubeMap>,
+    pub background: Background,
+    // world space, geometry on the negative side is cut away
+    pub clip_planes: Vec<math::Plane>,
 }
 
 #[derive(Debug)]
@@ -25,6 +112,18 @@ pub struct Face {
     pub uv0: i32,
     pub uv1: i32,
     pub uv2: i32,
+    // index into the object's materials
+    pub material: Option<usize>,
+    // obj "s" group, 0 when smoothing is off
+    pub smoothing_group: u32,
+}
+
+#[derive(Debug)]
+pub struct Transform {
+    pub position: math::Vector3,
+    // euler angles in radians, applied x, y then z
+    pub rotation: math::Vector3,
+    pub scale: math::Vector3,
 }
 
 #[derive(Debug)]
@@ -33,224 +132,803 @@ pub struct Object {
     pub faces: Vec<Face>,
     pub vertex_normals: Vec<math::Vector4>,
     pub uvs: Vec<math::Point>,
+    // rgb in 0..1, either empty or one per vertex
+    pub colors: Vec<math::Vector3>,
     pub texture: super::Texture,
+    // tangent space normal map for faces without a material
+    pub normal_map: Option<super::Texture>,
+    // one per face corner, xyz the tangent and w the bitangent sign.
+    // empty until generate_tangents
+    pub tangents: Vec<[math::Vector4; 3]>,
+    pub filter: super::TextureFilter,
+    pub uv_source: super::UvSource,
+    // one entry per usemtl name, in order of first use
+    pub materials: Vec<Material>,
+    // mtllib file names, for the caller to fetch
+    pub material_libs: Vec<String>,
+    pub transform: Transform,
+    // handle of the parent object, the local transform is relative to it
+    pub parent: Option<usize>,
+    pub visible: bool,
+    pub shading: super::ShadingMode,
+    pub specular: f32,
+    pub shininess: f32,
+    // cube map environment, drawn instead of the texture
+    pub environment: super::EnvironmentMapping,
+    pub cube_map: Option<usize>,
+    pub refractive_index: f32,
+    pub blend_mode: super::BlendMode,
+    pub alpha_cutoff: f32,
+    pub cull_mode: super::CullMode,
+    // object space bounds, kept up to date by update_bounds
+    pub bounds: math::Aabb,
+    pub bounding_sphere: math::Sphere,
+    // for faces without a material, materials use their own
+    pub opacity: f32,
+}
+
+// objects addressed by handle, slots of removed objects are never reused
+// so a stale handle can't end up pointing at a different object
+#[derive(Debug)]
+pub struct ObjectStore {
+    slots: Vec<Option<Object>>,
+}
+
+impl ObjectStore {
+    pub fn new() -> ObjectStore {
+        ObjectStore { slots: vec![] }
+    }
+
+    pub fn insert(&mut self, object: Object) -> usize {
+        self.slots.push(Some(object));
+        self.slots.len() - 1
+    }
+
+    pub fn remove(&mut self, handle: usize) -> Option<Object> {
+        self.slots.get_mut(handle).and_then(|slot| slot.take())
+    }
+
+    pub fn get(&self, handle: usize) -> Option<&Object> {
+        self.slots.get(handle).and_then(|slot| slot.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: usize) -> Option<&mut Object> {
+        self.slots.get_mut(handle).and_then(|slot| slot.as_mut())
+    }
+
+    pub fn contains(&self, handle: usize) -> bool {
+        self.get(handle).is_some()
+    }
+
+    // one past the largest handle handed out so far
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Object)> {
+        self.slots.iter().enumerate().filter_map(|(handle, slot)| slot.as_ref().map(|obj| (handle, obj)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut Object)> {
+        self.slots.iter_mut().enumerate().filter_map(|(handle, slot)| slot.as_mut().map(|obj| (handle, obj)))
+    }
+}
+
+impl Index<usize> for ObjectStore {
+    type Output = Object;
+
+    fn index(&self, handle: usize) -> &Object {
+        self.get(handle).expect("no object with this handle")
+    }
+}
+
+impl IndexMut<usize> for ObjectStore {
+    fn index_mut(&mut self, handle: usize) -> &mut Object {
+        self.get_mut(handle).expect("no object with this handle")
+    }
 }
 
 impl Camera {
     pub fn new() -> Camera {
-        Camera {
+        let mut camera = Camera {
             position: math::Vector3 { x: 0.0, y: 0.0, z: 1.0 },
-            target: math::Vector3 { x: 0.0, y: 0.0, z: 0.0},
+            orientation: math::Quaternion::identity(),
+            up: math::Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+            projection: super::ProjectionMode::Perspective,
             field_of_vision: 60.0,
-            aspect_ratio: 3.0 / 2.0,
-        }
+            orthographic_height: 2.0,
+            window: [-0.5, 0.5, -0.5, 0.5],
+            near: 1.0,
+            far: 1000.0,
+        };
+        camera.look_at(&math::Vector3::new());
+        camera
     }
-}
 
-impl Scene {
-    pub fn new() -> Scene {
-        let objs: Vec<Object> = vec![];
+    // turns towards target keeping up at the top of the screen. looking along
+    // up keeps the current roll instead of snapping around
+    pub fn look_at(&mut self, target: &math::Vector3) {
+        if target.sub(&self.position).len() < 1e-8 {
+            return;
+        }
+        let forward = target.sub(&self.position).normal();
+        let up = if self.up.normal().cross(&forward).len() < 1e-4 { self.axes().1 } else { self.up };
+        let rotation = math::Matrix4::lookat_rot(&self.position, target, &up);
+        self.orientation = math::Quaternion::from_matrix(&rotation).conjugate();
+    }
 
-        Scene { objects: objs, camera: Camera::new() }
+    // banks around the view direction, angle in radians, positive lifts the
+    // right side. up turns with it so following look_at calls keep the roll
+    pub fn roll(&mut self, angle: f32) {
+        let rotation = math::Quaternion::from_axis_angle(&self.axes().2, angle);
+        self.orientation = rotation.multiply(&self.orientation).normal();
+        self.up = rotation.rotate(&self.up);
     }
 
-    pub fn add_object(&mut self, object: Object) {
-        self.objects.push(object)
+    // right, up and forward in world space
+    pub fn axes(&self) -> (math::Vector3, math::Vector3, math::Vector3) {
+        let r = self.orientation.matrix();
+        (
+            math::Vector3 { x: r.m[0][0], y: r.m[0][1], z: r.m[0][2] },
+            math::Vector3 { x: r.m[1][0], y: r.m[1][1], z: r.m[1][2] },
+            math::Vector3 { x: r.m[2][0], y: r.m[2][1], z: r.m[2][2] },
+        )
     }
 
-    fn clip(vertices: Vec<&super::VertexUV>) -> Vec<super::VertexUV> {
-        let mut z1_out:Vec<super::VertexUV> = Vec::with_capacity(vertices.len() + 1);
+    pub fn view_rotation(&self) -> math::Matrix4 {
+        self.orientation.conjugate().matrix()
+    }
 
-        // near-z
-        let mut v_next_iter = vertices.iter().cycle();
-        v_next_iter.next();
-        for v in vertices.iter() {
-            let v_next = v_next_iter.next().unwrap();
+    pub fn view_matrix(&self) -> math::Matrix4 {
+        let p = &self.position;
+        self.view_rotation().translate_xyz(-p.x, -p.y, -p.z)
+    }
 
-            let dot = v.z + v.w;
-            let dot_next = v_next.z + v_next.w;
+    pub fn projection_matrix(&self, aspect_ratio: f32) -> math::Matrix4 {
+        match self.projection {
+            super::ProjectionMode::Perspective => {
+                math::Matrix4::projection(self.field_of_vision.to_radians(), aspect_ratio, self.near, self.far)
+            },
+            super::ProjectionMode::Orthographic => {
+                let top = self.orthographic_height / 2.0;
+                let right = top * aspect_ratio;
+                math::Matrix4::orthographic(-right, right, -top, top, self.near, self.far)
+            },
+            super::ProjectionMode::OffAxis => {
+                let [left, right, bottom, top] = self.window;
+                math::Matrix4::frustum(left, right, bottom, top, self.near, self.far)
+            },
+        }
+    }
 
-            if dot >= 0.0 {
-                z1_out.push(super::VertexUV{ x: v.x, y: v.y, z: v.z, w: v.w, u: v.u, v: v.v });
-            }
-            if dot < 0.0 && dot_next < 0.0 {
-                continue;
-            }
-            if dot.signum() != dot_next.signum() {
-                let a = (dot_next) / (dot_next - dot);
-                z1_out.push(v.lerp(v_next, a));
-            }
+    // view space ray through a point of the screen, x and y in -1..1 with y
+    // up. only the direction matters, so orthographic rays all point ahead
+    pub fn view_ray(&self, x: f32, y: f32, aspect_ratio: f32) -> math::Vector3 {
+        match self.projection {
+            super::ProjectionMode::Perspective => {
+                let tan = (self.field_of_vision.to_radians() / 2.0).tan();
+                math::Vector3 { x: x * tan * aspect_ratio, y: y * tan, z: 1.0 }
+            },
+            super::ProjectionMode::Orthographic => math::Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+            super::ProjectionMode::OffAxis => {
+                let [left, right, bottom, top] = self.window;
+                let wx = left + (x + 1.0) * 0.5 * (right - left);
+                let wy = bottom + (y + 1.0) * 0.5 * (top - bottom);
+                math::Vector3 { x: wx / self.near, y: wy / self.near, z: 1.0 }
+            },
         }
+    }
+}
 
-        // far-z
-        let mut z2_out:Vec<super::VertexUV> = Vec::with_capacity(z1_out.len() + 1);
+impl Light {
+    pub fn directional(direction: math::Vector3, color: math::Vector3, intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Directional,
+            position: math::Vector3::new(),
+            direction: direction.normal(),
+            color,
+            intensity,
+            range: 0.0,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+        }
+    }
 
-        let mut v_next_iter = z1_out.iter().cycle();
-        v_next_iter.next();
-        for v in z1_out.iter() {
-            let v_next = v_next_iter.next().unwrap();
+    pub fn point(position: math::Vector3, color: math::Vector3, intensity: f32, range: f32) -> Light {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: math::Vector3::new(),
+            color,
+            intensity,
+            range,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+        }
+    }
 
-            let dot = -v.z + v.w;
-            let dot_next = -v_next.z + v_next.w;
+    pub fn spot(position: math::Vector3, direction: math::Vector3, color: math::Vector3, intensity: f32, range: f32, inner_angle: f32, outer_angle: f32) -> Light {
+        Light {
+            kind: LightKind::Spot,
+            position,
+            direction: direction.normal(),
+            color,
+            intensity,
+            range,
+            inner_angle,
+            outer_angle: outer_angle.max(inner_angle),
+        }
+    }
 
-            if dot >= 0.0 {
-                z2_out.push(super::VertexUV{ x: v.x, y: v.y, z: v.z, w: v.w, u: v.u, v: v.v });
-            }
-            if dot < 0.0 && dot_next < 0.0 {
-                continue;
-            }
-            if dot.signum() != dot_next.signum() {
-                let a = (dot_next) / (dot_next - dot);
-                z2_out.push(v.lerp(v_next, a));
+    // blinn-phong diffuse and specular factors at a surface point,
+    // normal and view_dir are expected to be unit length
+    pub fn illuminate(&self, position: &math::Vector3, normal: &math::Vector3, view_dir: &math::Vector3, shininess: f32) -> (f32, f32) {
+        let (to_light, attenuation) = match self.kind {
+            LightKind::Directional => (self.direction.negate(), 1.0),
+            LightKind::Point | LightKind::Spot => {
+                let offset = self.position.sub(position);
+                let distance = offset.len();
+                if distance < 1e-6 {
+                    return (0.0, 0.0);
+                }
+                let falloff = if self.range > 0.0 {
+                    let f = (1.0 - (distance / self.range) * (distance / self.range)).max(0.0);
+                    f * f
+                } else {
+                    1.0
+                };
+                (offset.scale(1.0 / distance), falloff)
+            },
+        };
+
+        let cone = if self.kind == LightKind::Spot {
+            let cos_angle = to_light.negate().dot(&self.direction);
+            let cos_outer = self.outer_angle.cos();
+            let cos_inner = self.inner_angle.cos();
+            if cos_angle <= cos_outer {
+                0.0
+            } else if cos_angle >= cos_inner {
+                1.0
+            } else {
+                let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+                t * t * (3.0 - 2.0 * t)
             }
+        } else {
+            1.0
+        };
+
+        let n_dot_l = normal.dot(&to_light);
+        if n_dot_l <= 0.0 || attenuation * cone <= 0.0 {
+            return (0.0, 0.0);
         }
 
-        let mut x1_out:Vec<super::VertexUV> = Vec::with_capacity(z2_out.len() + 1);
-        // x < w
-        let mut v_next_iter = z1_out.iter().cycle();
-        v_next_iter.next();
-        for v in z2_out.iter() {
-            let v_next = v_next_iter.next().unwrap();
-            let dot = v.x + v.w;
-            let dot_next = v_next.x + v_next.w;
+        let half = to_light.add(view_dir).normal();
+        let n_dot_h = normal.dot(&half).max(0.0);
+        let specular = if shininess > 0.0 { n_dot_h.powf(shininess) } else { 0.0 };
 
-            if dot >= 0.0 {
-                x1_out.push(super::VertexUV{ x: v.x, y: v.y, z: v.z, w: v.w, u: v.u, v: v.v });
-            }
-            if dot < 0.0 && dot_next < 0.0 {
-                continue;
-            }
-            if dot.signum() != dot_next.signum() {
-                let a = (dot_next) / (dot_next - dot);
-                x1_out.push(v.lerp(v_next, a));
+        (n_dot_l * attenuation * cone, specular * attenuation * cone)
+    }
+}
+
+// sums up all lights, returns diffuse (ambient included) and specular color
+pub fn shade(lights: &[Light], ambient: &math::Vector3, position: &math::Vector3, normal: &math::Vector3, eye: &math::Vector3, specular: &math::Vector3, shininess: f32) -> (math::Vector3, math::Vector3) {
+    let view_dir = eye.sub(position).normal();
+    let mut diffuse_sum = *ambient;
+    let mut specular_sum = math::Vector3::new();
+
+    for light in lights.iter() {
+        let (d, s) = light.illuminate(position, normal, &view_dir, shininess);
+        let color = light.color.scale(light.intensity);
+        diffuse_sum = diffuse_sum.add(&color.scale(d));
+        specular_sum = specular_sum.add(&color.mul(specular).scale(s));
+    }
+
+    (diffuse_sum, specular_sum)
+}
+
+// the loader reverses the OBJ winding, so the edges are taken in reverse too
+fn face_normal(p0: &math::Vector3, p1: &math::Vector3, p2: &math::Vector3) -> math::Vector3 {
+    p2.sub(p0).cross(&p1.sub(p0)).normal()
+}
+
+fn indexes_valid(indexes: &[i32], len: usize) -> bool {
+    indexes.iter().all(|&i| i >= 0 && (i as usize) < len)
+}
+
+// determinant of the clip space x, y, w. its sign is the screen space
+// winding times the sign of the w's, so it works before the divide and
+// for triangles crossing the camera plane
+pub fn winding(a: &math::Vector4, b: &math::Vector4, c: &math::Vector4) -> f32 {
+    a.x * (b.y * c.w - c.y * b.w) - b.x * (a.y * c.w - c.y * a.w) + c.x * (a.y * b.w - b.y * a.w)
+}
+
+pub fn sphere_map_uv(view_normal: &math::Vector4) -> math::Point {
+    math::Point { x: (view_normal.x / -2.0) + 0.5, y: (view_normal.y / -2.0) + 0.5 }
+}
+
+// texture coordinates for the corners of a face, falls back to another
+// source when the mesh lacks the uvs or normals the chosen one needs
+fn texture_coordinates(obj: &Object, face: &Face, view_normals: &[math::Vector4], bounds: &math::Aabb) -> [math::Point; 3] {
+    let has_uvs = indexes_valid(&[face.uv0, face.uv1, face.uv2], obj.uvs.len());
+    let has_normals = indexes_valid(&[face.vn0, face.vn1, face.vn2], view_normals.len());
+
+    let source = match obj.uv_source {
+        super::UvSource::Mesh if !has_uvs => if has_normals { super::UvSource::SphereMap } else { super::UvSource::Planar },
+        super::UvSource::SphereMap if !has_normals => if has_uvs { super::UvSource::Mesh } else { super::UvSource::Planar },
+        source => source,
+    };
+
+    let (min, max) = (&bounds.min, &bounds.max);
+    let size = max.sub(min);
+    let extent = |d: f32| if d.abs() < 1e-8 { 1.0 } else { d };
+    let positions = [
+        &obj.vertices[face.v0 as usize],
+        &obj.vertices[face.v1 as usize],
+        &obj.vertices[face.v2 as usize],
+    ];
+
+    match source {
+        super::UvSource::Mesh => {
+            // OBJ has v going up, texture rows go down
+            let uv = |i: i32| {
+                let p = obj.uvs[i as usize];
+                math::Point { x: p.x, y: 1.0 - p.y }
+            };
+            [uv(face.uv0), uv(face.uv1), uv(face.uv2)]
+        },
+        super::UvSource::SphereMap => {
+            let uv = |i: i32| sphere_map_uv(&view_normals[i as usize]);
+            [uv(face.vn0), uv(face.vn1), uv(face.vn2)]
+        },
+        super::UvSource::Planar => {
+            let uv = |p: &math::Vector4| math::Point {
+                x: (p.x - min.x) / extent(size.x),
+                y: 1.0 - (p.y - min.y) / extent(size.y),
+            };
+            [uv(positions[0]), uv(positions[1]), uv(positions[2])]
+        },
+        super::UvSource::Cylindrical => {
+            let cx = (min.x + max.x) / 2.0;
+            let cz = (min.z + max.z) / 2.0;
+            let uv = |p: &math::Vector4| math::Point {
+                x: (p.z - cz).atan2(p.x - cx) / (2.0 * std::f32::consts::PI) + 0.5,
+                y: 1.0 - (p.y - min.y) / extent(size.y),
+            };
+            let mut uvs = [uv(positions[0]), uv(positions[1]), uv(positions[2])];
+
+            // faces crossing the seam would otherwise stretch over the whole texture
+            let max_u = uvs.iter().fold(f32::MIN, |m, p| m.max(p.x));
+            for p in uvs.iter_mut() {
+                if max_u - p.x > 0.5 {
+                    p.x += 1.0;
+                }
             }
+            uvs
+        },
+    }
+}
+
+impl Transform {
+    pub fn new() -> Transform {
+        Transform {
+            position: math::Vector3 { x: 0.0, y: 0.0, z: 0.0 },
+            rotation: math::Vector3 { x: 0.0, y: 0.0, z: 0.0 },
+            scale: math::Vector3 { x: 1.0, y: 1.0, z: 1.0 },
         }
+    }
 
-        let mut x2_out:Vec<super::VertexUV> = Vec::with_capacity(x1_out.len() + 1);
-        // x > -w
-        let mut v_next_iter = x1_out.iter().cycle();
-        v_next_iter.next();
-        for v in x1_out.iter() {
-            let v_next = v_next_iter.next().unwrap();
-            let dot = -v.x + v.w;
-            let dot_next = -v_next.x + v_next.w;
+    // local to parent matrix, scale then rotate then translate
+    pub fn matrix(&self) -> math::Matrix4 {
+        math::Matrix4::scaling(self.scale.x, self.scale.y, self.scale.z)
+            .multiply(&math::Matrix4::rotation_x(self.rotation.x))
+            .multiply(&math::Matrix4::rotation_y(self.rotation.y))
+            .multiply(&math::Matrix4::rotation_z(self.rotation.z))
+            .multiply(&math::Matrix4::identity().translate(&self.position))
+    }
 
-            if dot >= 0.0 {
-                x2_out.push(super::VertexUV{ x: v.x, y: v.y, z: v.z, w: v.w, u: v.u, v: v.v });
-            }
-            if dot < 0.0 && dot_next < 0.0 {
-                continue;
-            }
-            if dot.signum() != dot_next.signum() {
-                let a = (dot_next) / (dot_next - dot);
-                x2_out.push(v.lerp(v_next, a));
+    // inverse of matrix() for matrices without shear or mirroring
+    pub fn from_matrix(m: &math::Matrix4) -> Transform {
+        let row = |i: usize| math::Vector3 { x: m.m[i][0], y: m.m[i][1], z: m.m[i][2] };
+        let scale = math::Vector3 { x: row(0).len(), y: row(1).len(), z: row(2).len() };
+        let safe = |s: f32| if s.abs() < 1e-8 { 1.0 } else { s };
+        let r = [row(0).scale(1.0 / safe(scale.x)), row(1).scale(1.0 / safe(scale.y)), row(2).scale(1.0 / safe(scale.z))];
+
+        // the rotation is rx * ry * rz, so r[0].z is sin(y)
+        let sin_y = r[0].z.clamp(-1.0, 1.0);
+        let rotation = if sin_y.abs() < 0.99999 {
+            math::Vector3 {
+                x: (-r[1].z).atan2(r[2].z),
+                y: sin_y.asin(),
+                z: (-r[0].y).atan2(r[0].x),
             }
+        } else {
+            // gimbal lock, z is folded into x
+            math::Vector3 { x: r[2].y.atan2(r[1].y), y: sin_y.asin(), z: 0.0 }
+        };
+
+        Transform {
+            position: math::Vector3 { x: m.m[3][0], y: m.m[3][1], z: m.m[3][2] },
+            rotation,
+            scale,
+        }
+    }
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            objects: ObjectStore::new(),
+            camera: Camera::new(),
+            interpolation: super::TextureInterpolation::PerspectiveCorrect,
+            lights: vec![],
+            ambient: math::Vector3 { x: 0.2, y: 0.2, z: 0.2 },
+            blank_texture: super::Texture::blank(),
+            cube_maps: vec![],
+            background: Background::Solid(math::Vector3::new()),
+            clip_planes: vec![],
         }
+    }
+
+    pub fn add_object(&mut self, mut object: Object) -> usize {
+        object.update_bounds();
+        self.objects.insert(object)
+    }
 
-        let mut y1_out:Vec<super::VertexUV> = Vec::with_capacity(z2_out.len() + 1);
-        // y < w
-        let mut v_next_iter = x2_out.iter().cycle();
-        v_next_iter.next();
-        for v in x2_out.iter() {
-            let v_next = v_next_iter.next().unwrap();
-            let dot = v.y + v.w;
-            let dot_next = v_next.y + v_next.w;
+    pub fn add_cube_map(&mut self, cube_map: super::cubemap::CubeMap) -> usize {
+        self.cube_maps.push(cube_map);
+        self.cube_maps.len() - 1
+    }
 
-            if dot >= 0.0 {
-                y1_out.push(super::VertexUV{ x: v.x, y: v.y, z: v.z, w: v.w, u: v.u, v: v.v });
+    // children of the removed object are kept and become roots
+    pub fn remove_object(&mut self, handle: usize) -> Option<Object> {
+        let removed = self.objects.remove(handle);
+        if removed.is_some() {
+            for (_, obj) in self.objects.iter_mut() {
+                if obj.parent == Some(handle) {
+                    obj.parent = None;
+                }
             }
-            if dot < 0.0 && dot_next < 0.0 {
-                continue;
+        }
+        removed
+    }
+
+    // refuses parents that don't exist or would create a cycle
+    pub fn set_parent(&mut self, child: usize, parent: Option<usize>) -> bool {
+        if !self.objects.contains(child) {
+            return false;
+        }
+        let mut ancestor = parent;
+        while let Some(handle) = ancestor {
+            if handle == child || !self.objects.contains(handle) {
+                return false;
             }
-            if dot.signum() != dot_next.signum() {
-                let a = (dot_next) / (dot_next - dot);
-                y1_out.push(v.lerp(v_next, a));
+            ancestor = self.objects[handle].parent;
+        }
+        self.objects[child].parent = parent;
+        true
+    }
+
+    // an object is only drawn if it and all of its ancestors are visible
+    pub fn is_visible(&self, handle: usize) -> bool {
+        let mut current = Some(handle);
+        while let Some(h) = current {
+            match self.objects.get(h) {
+                Some(obj) if obj.visible => current = obj.parent,
+                _ => return false,
             }
         }
+        true
+    }
 
-        let mut y2_out:Vec<super::VertexUV> = Vec::with_capacity(x1_out.len() + 1);
-        // y > -w
-        let mut v_next_iter = y1_out.iter().cycle();
-        v_next_iter.next();
-        for v in y1_out.iter() {
-            let v_next = v_next_iter.next().unwrap();
-            let dot = -v.y + v.w;
-            let dot_next = -v_next.y + v_next.w;
+    fn resolve_world_matrix(&self, handle: usize, world: &mut Vec<Option<math::Matrix4>>) -> math::Matrix4 {
+        if let Some(matrix) = world[handle] {
+            return matrix;
+        }
+        let local = self.objects[handle].transform.matrix();
+        let matrix = match self.objects[handle].parent {
+            Some(parent) => local.multiply(&self.resolve_world_matrix(parent, world)),
+            None => local,
+        };
+        world[handle] = Some(matrix);
+        matrix
+    }
 
-            if dot >= 0.0 {
-                y2_out.push(super::VertexUV{ x: v.x, y: v.y, z: v.z, w: v.w, u: v.u, v: v.v });
-            }
-            if dot < 0.0 && dot_next < 0.0 {
-                continue;
+    // local to world matrix indexed by handle, parents composed in,
+    // removed handles get identity
+    pub fn world_matrices_into(&self, cache: &mut Vec<Option<math::Matrix4>>, world_matrices: &mut Vec<math::Matrix4>) {
+        cache.clear();
+        cache.resize(self.objects.capacity(), None);
+        world_matrices.clear();
+        world_matrices.extend((0..self.objects.capacity()).map(|handle| {
+            if self.objects.contains(handle) {
+                self.resolve_world_matrix(handle, cache)
+            } else {
+                math::Matrix4::identity()
             }
-            if dot.signum() != dot_next.signum() {
-                let a = (dot_next) / (dot_next - dot);
-                y2_out.push(v.lerp(v_next, a));
+        }));
+    }
+
+    // fills the whole color buffer, call before draw
+    pub fn draw_background(&self, render_target: &mut super::RenderTarget) {
+        let to_byte = |c: f32| (c * 255.0 + 0.5).clamp(0.0, 255.0) as u8;
+        let width = render_target.width as usize;
+        let height = render_target.height as usize;
+
+        let mut fill_rows = |row_color: &dyn Fn(usize) -> math::Vector3| {
+            for (y, row) in render_target.buffer.chunks_exact_mut(width * 4).enumerate() {
+                let color = row_color(y);
+                let pixel = [to_byte(color.x), to_byte(color.y), to_byte(color.z), 255];
+                for p in row.chunks_exact_mut(4) {
+                    p.copy_from_slice(&pixel);
+                }
             }
+        };
+
+        match self.background {
+            Background::Solid(color) => fill_rows(&|_| color),
+            Background::Gradient { top, bottom } => {
+                let rows = (height.max(2) - 1) as f32;
+                fill_rows(&|y| top.lerp(&bottom, 1.0 - y as f32 / rows));
+            },
+            Background::Skybox(cube_map) => {
+                let cube_map = match self.cube_maps.get(cube_map) {
+                    Some(c) => c,
+                    None => {
+                        render_target.buffer.chunks_exact_mut(4).for_each(|p| p.copy_from_slice(&[0, 0, 0, 255]));
+                        return;
+                    },
+                };
+
+                let (right, up, forward) = self.camera.axes();
+
+                // inverse of the projection, screen y points down
+                let aspect_ratio = render_target.aspect_ratio();
+                for (y, row) in render_target.buffer.chunks_exact_mut(width * 4).enumerate() {
+                    let sy = -((y as f32 + 0.5) / height as f32 * 2.0 - 1.0);
+                    for (x, p) in row.chunks_exact_mut(4).enumerate() {
+                        let sx = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+                        let ray = self.camera.view_ray(sx, sy, aspect_ratio);
+                        let direction = right.scale(ray.x).add(&up.scale(ray.y)).add(&forward.scale(ray.z));
+                        let c = cube_map.sample(super::TextureFilter::Bilinear, &direction);
+                        p.copy_from_slice(&[c.r, c.g, c.b, 255]);
+                    }
+                }
+            },
         }
+    }
 
-        return y2_out;
+    // visible objects, opaque ones first in handle order and then transparent
+    // ones from the farthest to the nearest so they blend over each other
+    pub fn draw_order_into(&self, world_matrices: &[math::Matrix4], order: &mut Vec<usize>, transparent: &mut Vec<(usize, f32)>) {
+        let distance = |handle: usize, obj: &Object| {
+            let center = obj.bounding_sphere.center;
+            let world = math::Vector4 { x: center.x, y: center.y, z: center.z, w: 1.0 }.multiply(&world_matrices[handle]);
+            let offset = math::Vector3 { x: world.x, y: world.y, z: world.z }.sub(&self.camera.position);
+            offset.dot(&offset)
+        };
+
+        order.clear();
+        transparent.clear();
+        for (handle, obj) in self.objects.iter().filter(|(handle, _)| self.is_visible(*handle)) {
+            if obj.blend_mode.is_transparent() {
+                transparent.push((handle, distance(handle, obj)));
+            } else {
+                order.push(handle);
+            }
+        }
+        // sort_unstable_by doesn't allocate, equal distances can come in any order
+        transparent.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        order.extend(transparent.iter().map(|(handle, _)| *handle));
     }
 
-    pub fn draw(&self, render_target: &mut super::RenderTarget) {
+    // objects outside the frustum are skipped before any vertex is transformed
+    pub fn draw(&self, render_target: &mut super::RenderTarget, scratch: &mut DrawScratch) -> FrameStats {
+        let mut stats = FrameStats::default();
 
-        let aspect_ratio = render_target.width as f32 / render_target.height as f32;
+        let aspect_ratio = render_target.aspect_ratio();
 
-        let identity_matrix = math::Matrix4::identity();
-        let view_matrix = math::Matrix4::lookat(&self.camera.position, &self.camera.target);
-        let view_rotation_matrix = math::Matrix4::lookat_rot(&self.camera.position, &self.camera.target);
-        let projection_matrix = math::Matrix4::projection(self.camera.field_of_vision / 180.0 * std::f32::consts::PI, aspect_ratio, 1.0, 1000.0);
-        let final_matrix = identity_matrix.multiply(&view_matrix).multiply(&projection_matrix);
+        self.world_matrices_into(&mut scratch.world_cache, &mut scratch.world_matrices);
+        let world_matrices = &scratch.world_matrices;
+        let view_matrix = self.camera.view_matrix();
+        let view_rotation_matrix = self.camera.view_rotation();
+        let projection_matrix = self.camera.projection_matrix(aspect_ratio);
+        let view_projection_matrix = view_matrix.multiply(&projection_matrix);
+        let frustum = math::Frustum::from_matrix(&view_projection_matrix);
+        let clip_planes = &mut scratch.clip_planes;
+        clip_planes.clear();
+        clip_planes.extend_from_slice(&super::clip::FRUSTUM_PLANES);
+        clip_planes.extend(self.clip_planes.iter().map(|p| super::clip::ClipPlane::World(*p)));
 
         let fw = render_target.width as f32;
         let fh = render_target.height as f32;
 
-        for obj in self.objects.iter() {
-            let transformed_vertices: Vec<math::Vector4> = obj.vertices.iter().map(|v| v.multiply(&final_matrix)).collect();
-            let transformed_normals: Vec<math::Vector4> = obj.vertex_normals.iter().map(|v| v.multiply(&view_rotation_matrix)).collect();
+        self.draw_order_into(world_matrices, &mut scratch.order, &mut scratch.transparent);
+        for &handle in scratch.order.iter() {
+            let obj = &self.objects[handle];
+            let world_matrix = &world_matrices[handle];
+            if !frustum.intersects_sphere(&obj.bounding_sphere.transform(world_matrix))
+                || !frustum.intersects_aabb(&obj.bounds.transform(world_matrix)) {
+                stats.objects_culled += 1;
+                continue;
+            }
+            stats.objects_drawn += 1;
+
+            let final_matrix = world_matrix.multiply(&view_projection_matrix);
+            let world_normal_matrix = world_matrix.normal_matrix();
+
+            let transformed_vertices = &mut scratch.transformed_vertices;
+            transformed_vertices.clear();
+            transformed_vertices.extend(obj.vertices.iter().map(|v| v.multiply(&final_matrix)));
+            let world_vertices = &mut scratch.world_vertices;
+            world_vertices.clear();
+            world_vertices.extend(obj.vertices.iter().map(|v| v.multiply(world_matrix)));
+            let world_normals = &mut scratch.world_normals;
+            world_normals.clear();
+            world_normals.extend(obj.vertex_normals.iter().map(|v| v.multiply(&world_normal_matrix).normal_xyz()));
+            let transformed_normals = &mut scratch.view_normals;
+            transformed_normals.clear();
+            transformed_normals.extend(world_normals.iter().map(|v| v.multiply(&view_rotation_matrix)));
+
+            let default_params = super::DrawParams {
+                texture: &obj.texture,
+                color: math::Vector3 { x: 1.0, y: 1.0, z: 1.0 },
+                interpolation: self.interpolation,
+                filter: obj.filter,
+                shading: obj.shading,
+                lights: &self.lights,
+                ambient: self.ambient,
+                eye: self.camera.position,
+                specular: math::Vector3 { x: obj.specular, y: obj.specular, z: obj.specular },
+                shininess: obj.shininess,
+                normal_map: obj.normal_map.as_ref(),
+                sphere_map: if obj.uv_source == super::UvSource::SphereMap { Some(view_rotation_matrix) } else { None },
+                environment: match obj.environment {
+                    super::EnvironmentMapping::Off => None,
+                    _ => obj.cube_map.and_then(|c| self.cube_maps.get(c)),
+                },
+                environment_mapping: obj.environment,
+                refractive_index: obj.refractive_index,
+                blend: obj.blend_mode,
+                alpha_cutoff: obj.alpha_cutoff,
+                opacity: obj.opacity,
+            };
+            let has_tangents = obj.tangents.len() == obj.faces.len();
+
+            // materials not loaded from a .mtl yet draw like faces without one
+            let material_params = |index: usize| {
+                let material = &obj.materials[index];
+                if !material.defined {
+                    return default_params;
+                }
+                super::DrawParams {
+                    texture: material.diffuse_texture.as_ref().unwrap_or(&self.blank_texture),
+                    color: material.diffuse,
+                    ambient: self.ambient.mul(&material.ambient),
+                    specular: material.specular,
+                    shininess: material.shininess,
+                    normal_map: material.normal_texture.as_ref(),
+                    opacity: material.opacity,
+                    ..default_params
+                }
+            };
+
+            let to_vector3 = |v: &math::Vector4| math::Vector3 { x: v.x, y: v.y, z: v.z };
+
+            for (face_index, face) in obj.faces.iter().enumerate() {
+                let mut params = match face.material {
+                    Some(m) => material_params(m),
+                    None => default_params,
+                };
+                let has_uvs = indexes_valid(&[face.uv0, face.uv1, face.uv2], obj.uvs.len());
+                if !has_tangents || !has_uvs {
+                    params.normal_map = None;
+                }
+                let params = &params;
 
-            for face in obj.faces.iter() {
                 let cv1 = &transformed_vertices[face.v0 as usize];
                 let cv2 = &transformed_vertices[face.v1 as usize];
                 let cv3 = &transformed_vertices[face.v2 as usize];
 
-                // let ax1 = cv3.sub(&cv1);
-                // let ax2 = cv2.sub(&cv1);
-                // let cp = ax2.cross(&ax1);
-                // if cp.z < 0.0 {
-                //     continue;
-                // }
-
-                if cv1.x.abs() > cv1.w.abs() && cv1.y.abs() > cv1.w.abs()
-                    && cv2.x.abs() > cv2.w.abs() && cv2.y.abs() > cv2.w.abs()
-                    && cv3.x.abs() > cv3.w.abs() && cv3.y.abs() > cv3.w.abs() {
+                // only an early out, the clipper drops anything else that is off screen.
+                // corners outside different planes can still cover the screen
+                let outside = |f: &dyn Fn(&math::Vector4) -> bool| f(cv1) && f(cv2) && f(cv3);
+                if outside(&|v| v.x > v.w) || outside(&|v| v.x < -v.w) || outside(&|v| v.y > v.w) || outside(&|v| v.y < -v.w) {
                     continue;
                 }
 
-                let euv0 = math::Point { x: (transformed_normals[face.vn0 as usize].x / -2.0) + 0.5, y: (transformed_normals[face.vn0 as usize].y / -2.0) + 0.5};
-                let euv1 = math::Point { x: (transformed_normals[face.vn1 as usize].x / -2.0) + 0.5, y: (transformed_normals[face.vn1 as usize].y / -2.0) + 0.5};
-                let euv2 = math::Point { x: (transformed_normals[face.vn2 as usize].x / -2.0) + 0.5, y: (transformed_normals[face.vn2 as usize].y / -2.0) + 0.5};
+                let [euv0, euv1, euv2] = texture_coordinates(obj, face, transformed_normals, &obj.bounds);
 
-                // let euv0 = obj.uvs[face.uv0 as usize];
-                // let euv1 = obj.uvs[face.uv1 as usize];
-                // let euv2 = obj.uvs[face.uv2 as usize];
-
-                let v1 = super::VertexUV{ x: cv1.x, y: cv1.y, z: cv1.z, w: cv1.w, u: euv0.x, v: euv0.y };
-                let v2 = super::VertexUV{ x: cv2.x, y: cv2.y, z: cv2.z, w: cv2.w, u: euv1.x, v: euv1.y };
-                let v3 = super::VertexUV{ x: cv3.x, y: cv3.y, z: cv3.z, w: cv3.w, u: euv2.x, v: euv2.y };
-
-                let to_clip = vec![
-                    &v1, 
-                    &v2,
-                    &v3,
+                let wp = [
+                    to_vector3(&world_vertices[face.v0 as usize]),
+                    to_vector3(&world_vertices[face.v1 as usize]),
+                    to_vector3(&world_vertices[face.v2 as usize]),
                 ];
+                let wn = if indexes_valid(&[face.vn0, face.vn1, face.vn2], world_normals.len()) {
+                    [
+                        to_vector3(&world_normals[face.vn0 as usize]),
+                        to_vector3(&world_normals[face.vn1 as usize]),
+                        to_vector3(&world_normals[face.vn2 as usize]),
+                    ]
+                } else {
+                    let n = face_normal(&wp[0], &wp[1], &wp[2]);
+                    [n, n, n]
+                };
+
+                // mesh uvs and tangent frames, only used with a normal map
+                let mesh_uv = |i: i32| if has_uvs { math::Point { x: obj.uvs[i as usize].x, y: 1.0 - obj.uvs[i as usize].y } } else { math::Point { x: 0.0, y: 0.0 } };
+                let muv = [mesh_uv(face.uv0), mesh_uv(face.uv1), mesh_uv(face.uv2)];
+                let mut tangents = [math::Vector3::new(); 3];
+                let mut bitangents = [math::Vector3::new(); 3];
+                if params.normal_map.is_some() {
+                    for corner in 0..3 {
+                        let t = &obj.tangents[face_index][corner];
+                        let world_t = to_vector3(&math::Vector4 { w: 0.0, ..*t }.multiply(world_matrix).normal_xyz());
+                        tangents[corner] = world_t;
+                        bitangents[corner] = wn[corner].cross(&world_t).scale(t.w);
+                    }
+                }
 
-                let clipped = self::Scene::clip(to_clip);
-
-                if clipped.len() == 0 {
+                let white = math::Vector3 { x: 1.0, y: 1.0, z: 1.0 };
+                let vc = if obj.colors.len() == obj.vertices.len() {
+                    [obj.colors[face.v0 as usize], obj.colors[face.v1 as usize], obj.colors[face.v2 as usize]]
+                } else {
+                    [white, white, white]
+                };
+
+                let shade_vertex = |position: &math::Vector3, normal: &math::Vector3| {
+                    shade(&self.lights, &params.ambient, position, normal, &self.camera.position, &params.specular, params.shininess)
+                };
+
+                let lit = match obj.shading {
+                    super::ShadingMode::Flat => {
+                        // face normal from the averaged vertex normals, lit at the centroid
+                        let centroid = wp[0].add(&wp[1]).add(&wp[2]).scale(1.0 / 3.0);
+                        let normal = wn[0].add(&wn[1]).add(&wn[2]).normal();
+                        let face_light = shade_vertex(&centroid, &normal);
+                        [face_light, face_light, face_light]
+                    },
+                    super::ShadingMode::Gouraud => [
+                        shade_vertex(&wp[0], &wn[0]),
+                        shade_vertex(&wp[1], &wn[1]),
+                        shade_vertex(&wp[2], &wn[2]),
+                    ],
+                    super::ShadingMode::Unlit | super::ShadingMode::Phong => {
+                        let none = (math::Vector3::new(), math::Vector3::new());
+                        [none, none, none]
+                    },
+                };
+
+                let v1 = super::VertexUV{ x: cv1.x, y: cv1.y, z: cv1.z, w: cv1.w, u: euv0.x, v: euv0.y,
+                    diffuse: lit[0].0, specular: lit[0].1, color: vc[0], position: wp[0], normal: wn[0],
+                    mesh_u: muv[0].x, mesh_v: muv[0].y, tangent: tangents[0], bitangent: bitangents[0] };
+                let v2 = super::VertexUV{ x: cv2.x, y: cv2.y, z: cv2.z, w: cv2.w, u: euv1.x, v: euv1.y,
+                    diffuse: lit[1].0, specular: lit[1].1, color: vc[1], position: wp[1], normal: wn[1],
+                    mesh_u: muv[1].x, mesh_v: muv[1].y, tangent: tangents[1], bitangent: bitangents[1] };
+                let v3 = super::VertexUV{ x: cv3.x, y: cv3.y, z: cv3.z, w: cv3.w, u: euv2.x, v: euv2.y,
+                    diffuse: lit[2].0, specular: lit[2].1, color: vc[2], position: wp[2], normal: wn[2],
+                    mesh_u: muv[2].x, mesh_v: muv[2].y, tangent: tangents[2], bitangent: bitangents[2] };
+
+                // front faces are the ones the rasterizer fills as they are,
+                // back faces get their order swapped when they're drawn too
+                let front = match winding(cv1, cv2, cv3) {
+                    w if w < 0.0 => true,
+                    w if w > 0.0 => false,
+                    _ => continue,
+                };
+                let clipped = &mut scratch.polygon;
+                clipped.clear();
+                match (obj.cull_mode, front) {
+                    (super::CullMode::Back, false) | (super::CullMode::Front, true) => continue,
+                    (_, true) => clipped.extend_from_slice(&[v1, v2, v3]),
+                    (_, false) => clipped.extend_from_slice(&[v1, v3, v2]),
+                };
+
+                super::clip::clip_polygon_into(clipped, &mut scratch.clip_output, clip_planes);
+                if clipped.is_empty() {
                     continue;
                 }
 
+                stats.triangles_drawn += (clipped.len() - 2) as u32;
                 for i in 0..clipped.len() - 2 {
                     let v1 = &clipped[0];
                     let v2 = &clipped[i+1];
@@ -266,14 +944,15 @@ impl Scene {
                     let y3 = (v3.y / v3.w) * (fh / 2.0) + (fh / 2.0);  
 
                     super::draw_triangle_barycentric_z_uv(render_target,
-                        &obj.texture,
-                        &super::VertexUV{ x: x1, y: y1, z: v1.z / v1.w, w: 1.0, u: v1.u, v: v1.v },
-                        &super::VertexUV{ x: x2, y: y2, z: v2.z / v2.w, w: 1.0, u: v2.u, v: v2.v },
-                        &super::VertexUV{ x: x3, y: y3, z: v3.z / v3.w, w: 1.0, u: v3.u, v: v3.v },
+                        params,
+                        &super::VertexUV{ x: x1, y: y1, z: v1.z / v1.w, w: 1.0 / v1.w, ..*v1 },
+                        &super::VertexUV{ x: x2, y: y2, z: v2.z / v2.w, w: 1.0 / v2.w, ..*v2 },
+                        &super::VertexUV{ x: x3, y: y3, z: v3.z / v3.w, w: 1.0 / v3.w, ..*v3 },
                         );
                 }
             }
         }
+        stats
     }
 }
 
@@ -284,162 +963,637 @@ impl Object {
         let vns: Vec<math::Vector4> = Vec::new();
         let uvs: Vec<math::Point> = Vec::new();
 
-        Object { vertices: vs, faces: fs, vertex_normals: vns, uvs: uvs, texture: super::Texture::new() }
+        Object {
+            vertices: vs,
+            faces: fs,
+            vertex_normals: vns,
+            uvs: uvs,
+            colors: vec![],
+            texture: super::Texture::new(),
+            normal_map: None,
+            tangents: vec![],
+            filter: super::TextureFilter::Nearest,
+            uv_source: super::UvSource::SphereMap,
+            materials: vec![],
+            material_libs: vec![],
+            transform: Transform::new(),
+            parent: None,
+            visible: true,
+            shading: super::ShadingMode::Unlit,
+            specular: 0.5,
+            shininess: 32.0,
+            environment: super::EnvironmentMapping::Off,
+            cube_map: None,
+            refractive_index: 1.5,
+            blend_mode: super::BlendMode::Opaque,
+            alpha_cutoff: 0.5,
+            cull_mode: super::CullMode::Back,
+            bounds: math::Aabb::empty(),
+            bounding_sphere: math::Sphere { center: math::Vector3::new(), radius: 0.0 },
+            opacity: 1.0,
+        }
+    }
+
+    // call after changing vertices
+    pub fn update_bounds(&mut self) {
+        let points: Vec<math::Vector3> = self.vertices.iter().map(|v| math::Vector3 { x: v.x, y: v.y, z: v.z }).collect();
+        let mut bounds = math::Aabb::empty();
+        points.iter().for_each(|p| bounds.extend(p));
+        self.bounds = bounds;
+        self.bounding_sphere = math::Sphere::around(&points, &bounds);
+    }
+
+    // turns every face around, for files wound the other way than obj.
+    // normals are left as they are
+    pub fn flip_winding(&mut self) {
+        for face in self.faces.iter_mut() {
+            mem::swap(&mut face.v0, &mut face.v2);
+            mem::swap(&mut face.vn0, &mut face.vn2);
+            mem::swap(&mut face.uv0, &mut face.uv2);
+        }
+        for frame in self.tangents.iter_mut() {
+            frame.swap(0, 2);
+        }
+    }
+
+    // replaces the normals of all faces. smooth normals are the area weighted average
+    // of the faces around a vertex position, leaving out faces bent away by more
+    // than crease_angle (radians)
+    pub fn generate_normals(&mut self, mode: super::NormalGeneration, crease_angle: f32) {
+        let position = |v: &math::Vector4| math::Vector3 { x: v.x, y: v.y, z: v.z };
+        let key = |v: &math::Vector4| [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()];
+
+        // not normalized, the length is twice the face area
+        let face_normals: Vec<Option<math::Vector3>> = self.faces.iter().map(|face| {
+            if !indexes_valid(&[face.v0, face.v1, face.v2], self.vertices.len()) {
+                return None;
+            }
+            let p0 = position(&self.vertices[face.v0 as usize]);
+            let p1 = position(&self.vertices[face.v1 as usize]);
+            let p2 = position(&self.vertices[face.v2 as usize]);
+            Some(p2.sub(&p0).cross(&p1.sub(&p0)))
+        }).collect();
+        let group = |face: &Face| match mode {
+            super::NormalGeneration::Flat => 0,
+            super::NormalGeneration::Smooth => 1,
+            super::NormalGeneration::SmoothingGroups => face.smoothing_group,
+        };
+
+        // faces around each distinct position, so seams with split vertices still smooth
+        let mut adjacent: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (i, face) in self.faces.iter().enumerate() {
+            if face_normals[i].is_some() {
+                for &v in [face.v0, face.v1, face.v2].iter() {
+                    adjacent.entry(key(&self.vertices[v as usize])).or_default().push(i);
+                }
+            }
+        }
+
+        let min_cos = crease_angle.cos() - 1e-6;
+        let mut normals: Vec<math::Vector4> = vec![];
+        // identical normals are stored once
+        let mut lookup: HashMap<[u32; 3], i32> = HashMap::new();
+        let mut face_vn: Vec<[i32; 3]> = Vec::with_capacity(self.faces.len());
+
+        for (i, face) in self.faces.iter().enumerate() {
+            let own = match face_normals[i] {
+                Some(n) => n.normal(),
+                None => {
+                    face_vn.push([-1, -1, -1]);
+                    continue;
+                },
+            };
+            let g = group(face);
+
+            let mut vn = [-1; 3];
+            for (corner, &v) in [face.v0, face.v1, face.v2].iter().enumerate() {
+                let n = if g == 0 {
+                    own
+                } else {
+                    adjacent[&key(&self.vertices[v as usize])].iter()
+                        .filter(|&&j| group(&self.faces[j]) == g)
+                        .map(|&j| face_normals[j].unwrap())
+                        .filter(|n| n.normal().dot(&own) >= min_cos)
+                        .fold(math::Vector3::new(), |sum, n| sum.add(&n))
+                        .normal()
+                };
+                vn[corner] = *lookup.entry([n.x.to_bits(), n.y.to_bits(), n.z.to_bits()]).or_insert_with(|| {
+                    normals.push(math::Vector4 { x: n.x, y: n.y, z: n.z, w: 0.0 });
+                    normals.len() as i32 - 1
+                });
+            }
+            face_vn.push(vn);
+        }
+
+        self.vertex_normals = normals;
+        for (face, vn) in self.faces.iter_mut().zip(face_vn) {
+            face.vn0 = vn[0];
+            face.vn1 = vn[1];
+            face.vn2 = vn[2];
+        }
+        // the tangent frames are built around the normals
+        if !self.tangents.is_empty() {
+            self.generate_tangents();
+        }
+    }
+
+    pub fn has_normal_maps(&self) -> bool {
+        self.normal_map.is_some() || self.materials.iter().any(|m| m.normal_texture.is_some())
+    }
+
+    // per corner tangents from the uv layout, averaged over faces sharing the
+    // same vertex, uv and normal. faces without uvs get a zero tangent
+    pub fn generate_tangents(&mut self) {
+        let position = |v: &math::Vector4| math::Vector3 { x: v.x, y: v.y, z: v.z };
+        let mut sums: HashMap<(i32, i32, i32), (math::Vector3, math::Vector3)> = HashMap::new();
+
+        for face in self.faces.iter() {
+            if !indexes_valid(&[face.v0, face.v1, face.v2], self.vertices.len()) || !indexes_valid(&[face.uv0, face.uv1, face.uv2], self.uvs.len()) {
+                continue;
+            }
+            let p0 = position(&self.vertices[face.v0 as usize]);
+            let e1 = position(&self.vertices[face.v1 as usize]).sub(&p0);
+            let e2 = position(&self.vertices[face.v2 as usize]).sub(&p0);
+            let uv0 = self.uvs[face.uv0 as usize];
+            let (du1, dv1) = (self.uvs[face.uv1 as usize].x - uv0.x, self.uvs[face.uv1 as usize].y - uv0.y);
+            let (du2, dv2) = (self.uvs[face.uv2 as usize].x - uv0.x, self.uvs[face.uv2 as usize].y - uv0.y);
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let r = 1.0 / det;
+            let t = e1.scale(dv2).sub(&e2.scale(dv1)).scale(r);
+            let b = e2.scale(du1).sub(&e1.scale(du2)).scale(r);
+
+            for &key in [(face.v0, face.uv0, face.vn0), (face.v1, face.uv1, face.vn1), (face.v2, face.uv2, face.vn2)].iter() {
+                let sum = sums.entry(key).or_insert((math::Vector3::new(), math::Vector3::new()));
+                *sum = (sum.0.add(&t), sum.1.add(&b));
+            }
+        }
+
+        let tangents = self.faces.iter().map(|face| {
+            let corners = [(face.v0, face.uv0, face.vn0), (face.v1, face.uv1, face.vn1), (face.v2, face.uv2, face.vn2)];
+            let mut frame = [math::Vector4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }; 3];
+            let has_normals = indexes_valid(&[face.vn0, face.vn1, face.vn2], self.vertex_normals.len());
+            let flat = if indexes_valid(&[face.v0, face.v1, face.v2], self.vertices.len()) {
+                face_normal(&position(&self.vertices[face.v0 as usize]), &position(&self.vertices[face.v1 as usize]), &position(&self.vertices[face.v2 as usize]))
+            } else {
+                math::Vector3::new()
+            };
+
+            for (corner, key) in corners.iter().enumerate() {
+                let (t, b) = match sums.get(key) {
+                    Some(sum) => *sum,
+                    None => continue,
+                };
+                let n = if has_normals { position(&self.vertex_normals[key.2 as usize]).normal() } else { flat };
+                // gram-schmidt against the normal
+                let t = t.sub(&n.scale(n.dot(&t))).normal();
+                let sign = if n.cross(&t).dot(&b) < 0.0 { -1.0 } else { 1.0 };
+                frame[corner] = math::Vector4 { x: t.x, y: t.y, z: t.z, w: sign };
+            }
+            frame
+        }).collect();
+        self.tangents = tangents;
+    }
+
+    // fills in the definitions of used materials from a parsed .mtl,
+    // returns how many of them matched
+    pub fn apply_materials(&mut self, loaded: Vec<Material>) -> usize {
+        let mut matched = 0;
+        for material in loaded.into_iter() {
+            if let Some(existing) = self.materials.iter_mut().find(|m| m.name == material.name) {
+                existing.update_from(material);
+                matched += 1;
+            }
+        }
+        matched
+    }
+
+    pub fn missing_textures(&self) -> Vec<String> {
+        let mut missing: Vec<String> = vec![];
+        for name in self.materials.iter().flat_map(|m| m.missing_textures()) {
+            if !missing.contains(&name) {
+                missing.push(name);
+            }
+        }
+        missing
+    }
+
+    // hands the image for a texture file to every material map that uses it,
+    // returns the number of maps resolved
+    pub fn set_texture_file(&mut self, file_name: &str, texture: &super::Texture) -> usize {
+        let mut resolved = 0;
+        for material in self.materials.iter_mut() {
+            if material.diffuse_map.as_deref() == Some(file_name) {
+                material.diffuse_texture = Some(texture.clone());
+                resolved += 1;
+            }
+            if material.normal_map.as_deref() == Some(file_name) {
+                material.normal_texture = Some(texture.clone());
+                resolved += 1;
+            }
+        }
+        if self.has_normal_maps() && self.tangents.len() != self.faces.len() {
+            self.generate_tangents();
+        }
+        resolved
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjErrorKind {
+    InvalidNumber(String),
+    MissingValues { expected: usize, found: usize },
+    TooFewFaceVertices(usize),
+    IndexOutOfRange { element: &'static str, index: i32, count: usize },
+}
+
+impl fmt::Display for ObjErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjErrorKind::InvalidNumber(token) => write!(f, "invalid number '{}'", token),
+            ObjErrorKind::MissingValues { expected, found } => write!(f, "expected {} values, found {}", expected, found),
+            ObjErrorKind::TooFewFaceVertices(count) => write!(f, "face needs at least 3 vertices, found {}", count),
+            ObjErrorKind::IndexOutOfRange { element, index, count } => write!(f, "{} index {} out of range, there are {}", element, index, count),
+        }
     }
 }
 
-pub struct ObjLoader {
+// line and column are 1-based, the column points at the offending token
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ObjErrorKind,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.kind)
+    }
+}
+
+// where a face came from, for reporting bad indexes after the whole file is read
+struct FaceSource {
+    line: usize,
+    columns: [usize; 3],
+}
+
+// one vertex of a face statement, indexes are 0-based and -1 when not given
+struct Corner {
+    v: i32,
+    uv: i32,
+    vn: i32,
+    column: usize,
+}
+
+// negative indexes count back from the last element read so far, -1 being the last
+fn resolve_index(index: i32, count: usize) -> i32 {
+    if index < 0 {
+        count as i32 + index + 1
+    } else {
+        index
+    }
+}
+
+// twice the signed area of a 2d triangle, positive when counter-clockwise
+fn area2(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+// splits a planar polygon into triangles by ear clipping, keeping its winding.
+// works for concave polygons, returns indexes into polygon
+pub fn triangulate_polygon(polygon: &[math::Vector4]) -> Vec<[usize; 3]> {
+    let n = polygon.len();
+    if n < 3 {
+        return vec![];
+    }
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // newell normal, then drop its largest axis to work in 2d
+    let (mut nx, mut ny, mut nz) = (0.0, 0.0, 0.0);
+    for i in 0..n {
+        let p = &polygon[i];
+        let q = &polygon[(i + 1) % n];
+        nx += (p.y - q.y) * (p.z + q.z);
+        ny += (p.z - q.z) * (p.x + q.x);
+        nz += (p.x - q.x) * (p.y + q.y);
+    }
+    let (ax, ay, az) = (f32::abs(nx), f32::abs(ny), f32::abs(nz));
+    // the projection is chosen so the polygon always comes out counter-clockwise
+    let points: Vec<(f32, f32)> = polygon.iter().map(|p| {
+        if az >= ax && az >= ay {
+            if nz >= 0.0 { (p.x, p.y) } else { (p.y, p.x) }
+        } else if ax >= ay {
+            if nx >= 0.0 { (p.y, p.z) } else { (p.z, p.y) }
+        } else if ny >= 0.0 {
+            (p.z, p.x)
+        } else {
+            (p.x, p.z)
+        }
+    }).collect();
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let is_ear = |i: usize| {
+            let (prev, cur, next) = (remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]);
+            let (a, b, c) = (points[prev], points[cur], points[next]);
+            if area2(a, b, c) <= 0.0 {
+                return false;
+            }
+            // no other corner may lie inside the ear
+            remaining.iter()
+                .filter(|&&j| j != prev && j != cur && j != next)
+                .all(|&j| {
+                    let p = points[j];
+                    area2(a, b, p) < 0.0 || area2(b, c, p) < 0.0 || area2(c, a, p) < 0.0
+                })
+        };
+
+        // degenerate polygons have no proper ear, clip the first corner to make progress
+        let i = (0..count).find(|&i| is_ear(i)).unwrap_or(0);
+        triangles.push([remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+// whitespace separated tokens with their 1-based column
+fn tokenize(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut start: Option<(usize, usize)> = None;
+    for (column, (i, c)) in line.char_indices().enumerate() {
+        if c.is_whitespace() {
+            if let Some((s, col)) = start.take() {
+                tokens.push((col, &line[s..i]));
+            }
+        } else if start.is_none() {
+            start = Some((i, column + 1));
+        }
+    }
+    if let Some((s, col)) = start {
+        tokens.push((col, &line[s..]));
+    }
+    tokens
+}
 
+// in lenient mode problems are collected as warnings, bad numbers read as
+// zero and broken statements are skipped instead of failing the load
+pub struct ObjLoader {
+    lenient: bool,
+    line: usize,
+    warnings: Vec<ObjError>,
 }
 
 impl ObjLoader {
-    fn parse_point(parts: &[&str]) -> Option<math::Point> {
-        if parts.len() < 2 {
-            return None;
-        }
-        let x = parts[0].parse::<f32>();
-        let y = parts[1].parse::<f32>();
-        return Some(math::Point {
-            x: if x.is_ok() { x.unwrap() } else { 0.0 }, 
-            y: if y.is_ok() { y.unwrap() } else { 0.0 },
-        });
-    }
-
-    fn parse_vertex(parts: &[&str]) -> Option<math::Vector4> {
-        if parts.len() < 3 {
-            return None;
-        }
-        let x = parts[0].parse::<f32>();
-        let y = parts[1].parse::<f32>();
-        let z = parts[2].parse::<f32>();
-        return Some(math::Vector4 {
-            x: if x.is_ok() { x.unwrap() } else { 0.0 }, 
-            y: if y.is_ok() { y.unwrap() } else { 0.0 },
-            z: if z.is_ok() { z.unwrap() } else { 0.0 },
-            w: 1.0,
-        });
+    fn new(lenient: bool) -> ObjLoader {
+        ObjLoader { lenient, line: 0, warnings: vec![] }
     }
 
-    fn parse_face_indexes(index_string: &str) -> (i32, i32, i32) {
-        let indexes: Vec<&str> = index_string.split('/').collect();
+    fn report(&mut self, column: usize, kind: ObjErrorKind) -> Result<(), ObjError> {
+        let error = ObjError { line: self.line, column, kind };
+        if self.lenient {
+            self.warnings.push(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
 
-        let mut fi: i32 = 0;
-        let mut uvi: i32 = 0;
-        let mut vni: i32 = 0;
+    fn parse_float(&mut self, token: (usize, &str)) -> Result<f32, ObjError> {
+        match token.1.parse::<f32>() {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                self.report(token.0, ObjErrorKind::InvalidNumber(token.1.to_string()))?;
+                Ok(0.0)
+            },
+        }
+    }
 
-        if indexes.len() > 0 {
-            let res = indexes[0].parse::<i32>();
-            fi = res.unwrap_or(0);
+    // false when the statement should be skipped
+    fn expect_values(&mut self, column: usize, parts: &[(usize, &str)], expected: usize) -> Result<bool, ObjError> {
+        if parts.len() < expected {
+            self.report(column, ObjErrorKind::MissingValues { expected, found: parts.len() })?;
+            return Ok(false);
         }
-        if indexes.len() > 1 {
-            let res = indexes[1].parse::<i32>();
-            uvi = res.unwrap_or(0);
+        Ok(true)
+    }
+
+    fn parse_point(&mut self, column: usize, parts: &[(usize, &str)]) -> Result<Option<math::Point>, ObjError> {
+        if !self.expect_values(column, parts, 2)? {
+            return Ok(None);
         }
-        if indexes.len() > 2 {
-            let res = indexes[2].parse::<i32>();
-            vni = res.unwrap_or(0);
+        Ok(Some(math::Point {
+            x: self.parse_float(parts[0])?,
+            y: self.parse_float(parts[1])?,
+        }))
+    }
+
+    fn parse_vertex(&mut self, column: usize, parts: &[(usize, &str)]) -> Result<Option<math::Vector4>, ObjError> {
+        if !self.expect_values(column, parts, 3)? {
+            return Ok(None);
         }
+        Ok(Some(math::Vector4 {
+            x: self.parse_float(parts[0])?,
+            y: self.parse_float(parts[1])?,
+            z: self.parse_float(parts[2])?,
+            w: 1.0,
+        }))
+    }
+
+    // "v", "v/vt", "v//vn" or "v/vt/vn", missing parts are 0
+    fn parse_face_indexes(&mut self, token: (usize, &str)) -> Result<(i32, i32, i32), ObjError> {
+        let mut indexes = [0i32; 3];
+        let mut offset = 0;
+
+        for (i, part) in token.1.split('/').take(3).enumerate() {
+            if !part.is_empty() {
+                match part.parse::<i32>() {
+                    Ok(index) => indexes[i] = index,
+                    Err(_) => self.report(token.0 + offset, ObjErrorKind::InvalidNumber(part.to_string()))?,
+                }
+            }
+            offset += part.chars().count() + 1;
+        }
+
+        Ok((indexes[0], indexes[1], indexes[2]))
+    }
+
+    // drops (lenient) or rejects faces pointing outside of the parsed data
+    fn validate_faces(&mut self, obj: &mut Object, sources: Vec<FaceSource>) -> Result<(), ObjError> {
+        let faces = std::mem::take(&mut obj.faces);
+
+        for (face, source) in faces.into_iter().zip(sources) {
+            self.line = source.line;
+            let corners = [
+                (face.v0, face.uv0, face.vn0),
+                (face.v1, face.uv1, face.vn1),
+                (face.v2, face.uv2, face.vn2),
+            ];
+
+            let mut valid = true;
+            for (corner, &(v, uv, vn)) in corners.iter().enumerate() {
+                let column = source.columns[corner];
+                // uv and normal indexes are optional, -1 means not given
+                let checks = [
+                    ("vertex", v, obj.vertices.len(), false),
+                    ("texture coordinate", uv, obj.uvs.len(), true),
+                    ("normal", vn, obj.vertex_normals.len(), true),
+                ];
+                for &(element, index, count, optional) in checks.iter() {
+                    if optional && index == -1 {
+                        continue;
+                    }
+                    if index < 0 || index as usize >= count {
+                        self.report(column, ObjErrorKind::IndexOutOfRange { element, index: index + 1, count })?;
+                        valid = false;
+                    }
+                }
+            }
 
-        return (
-            fi,
-            uvi,
-            vni,
-        );
+            if valid {
+                obj.faces.push(face);
+            }
+        }
+        Ok(())
     }
 
-    pub fn load_obj(file_as_string: String) -> Object {
+    fn load(&mut self, file_as_string: &str) -> Result<Object, ObjError> {
         let mut obj = self::Object::new();
+        let mut current_material: Option<usize> = None;
+        let mut current_smoothing_group = 0;
+        let mut face_sources: Vec<FaceSource> = vec![];
 
-        for line in file_as_string.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
+        for (line_index, line) in file_as_string.lines().enumerate() {
+            self.line = line_index + 1;
+            let parts = tokenize(line);
 
-            if parts.len() == 0 {
+            if parts.is_empty() {
                 continue;
             }
 
-            let entry_type = parts[0];
+            let (column, entry_type) = parts[0];
             let entry_data = &parts[1..];
 
-            match entry_type.as_ref() {
+            match entry_type {
                 "v" => {
-                    match self::ObjLoader::parse_vertex(entry_data) {
-                        Some(vertex) => obj.vertices.push(vertex),
-                        None => continue,
+                    if let Some(vertex) = self.parse_vertex(column, entry_data)? {
+                        obj.vertices.push(vertex);
                     }
                 },
                 "vn" => {
-                    match self::ObjLoader::parse_vertex(entry_data) {
-                        Some(normal) => obj.vertex_normals.push(normal),
-                        None => continue,
+                    if let Some(normal) = self.parse_vertex(column, entry_data)? {
+                        obj.vertex_normals.push(normal);
                     }
                 },
                 "vt" => {
-                    match self::ObjLoader::parse_point(entry_data) {
-                        Some(uv) => obj.uvs.push(uv),
-                        None => continue,
+                    if let Some(uv) = self.parse_point(column, entry_data)? {
+                        obj.uvs.push(uv);
                     }
                 },
                 "f" => {
-                    // log!("{:?} {:?}", entry_data, entry_data.len());
-
-                    if entry_data.len() != 3 && entry_data.len() != 4 {
+                    if entry_data.len() < 3 {
+                        self.report(column, ObjErrorKind::TooFewFaceVertices(entry_data.len()))?;
                         continue;
                     }
 
-                    let (i1, uvi1, vni1) = self::ObjLoader::parse_face_indexes(entry_data[0]);
-                    let (i2, uvi2, vni2) = self::ObjLoader::parse_face_indexes(entry_data[1]);
-                    let (i3, uvi3, vni3) = self::ObjLoader::parse_face_indexes(entry_data[2]);
-                    
-                    if entry_data.len() == 3 {
-                        // triangle
-                        let face = Face {
-                            v0: i3 - 1,
-                            v1: i2 - 1,
-                            v2: i1 - 1,
-                            vn0: vni3 - 1,
-                            vn1: vni2 - 1,
-                            vn2: vni1 - 1,
-                            uv0: uvi3 - 1,
-                            uv1: uvi2 - 1,
-                            uv2: uvi1 - 1,
-                        };
-                        obj.faces.push(face);
-                    } else if entry_data.len() == 4 {
-                        let (i4, uvi4, vni4) = self::ObjLoader::parse_face_indexes(entry_data[3]);
-
-                        let face = Face {
-                            v0: i4 - 1,
-                            v1: i2 - 1,
-                            v2: i1 - 1,
-                            vn0: vni4 - 1,
-                            vn1: vni2 - 1,
-                            vn2: vni1 - 1,
-                            uv0: uvi4 - 1,
-                            uv1: uvi2 - 1,
-                            uv2: uvi1 - 1,
-                        };
-                        obj.faces.push(face);
-
-                        let face2 = Face {
-                            v0: i4 - 1,
-                            v1: i3 - 1,
-                            v2: i2 - 1,
-                            vn0: vni4 - 1,
-                            vn1: vni3 - 1,
-                            vn2: vni2 - 1,
-                            uv0: uvi4 - 1,
-                            uv1: uvi3 - 1,
-                            uv2: uvi2 - 1,
-                        };
-                        obj.faces.push(face2);
+                    let mut corners = Vec::with_capacity(entry_data.len());
+                    for &token in entry_data {
+                        let (v, uv, vn) = self.parse_face_indexes(token)?;
+                        corners.push(Corner {
+                            v: resolve_index(v, obj.vertices.len()) - 1,
+                            uv: resolve_index(uv, obj.uvs.len()) - 1,
+                            vn: resolve_index(vn, obj.vertex_normals.len()) - 1,
+                            column: token.0,
+                        });
                     }
+
+                    // bad indexes are reported by validate_faces, a fan keeps the corners around for that
+                    let positions: Option<Vec<math::Vector4>> = corners.iter()
+                        .map(|c| if c.v >= 0 { obj.vertices.get(c.v as usize).cloned() } else { None })
+                        .collect();
+                    let triangles = match positions {
+                        Some(positions) => triangulate_polygon(&positions),
+                        None => (1..corners.len() - 1).map(|i| [0, i, i + 1]).collect(),
+                    };
+
+                    // faces are stored with reversed winding
+                    for [a, b, c] in triangles {
+                        let (c0, c1, c2) = (&corners[c], &corners[b], &corners[a]);
+                        obj.faces.push(Face {
+                            v0: c0.v,
+                            v1: c1.v,
+                            v2: c2.v,
+                            vn0: c0.vn,
+                            vn1: c1.vn,
+                            vn2: c2.vn,
+                            uv0: c0.uv,
+                            uv1: c1.uv,
+                            uv2: c2.uv,
+                            material: current_material,
+                            smoothing_group: current_smoothing_group,
+                        });
+                        face_sources.push(FaceSource { line: self.line, columns: [c0.column, c1.column, c2.column] });
+                    }
+                },
+                "mtllib" => {
+                    // file names may contain spaces
+                    obj.material_libs.push(entry_data.iter().map(|t| t.1).collect::<Vec<&str>>().join(" "));
+                },
+                "s" => {
+                    current_smoothing_group = match entry_data.first() {
+                        Some(&(_, "off")) | None => 0,
+                        Some(&token) => match token.1.parse::<u32>() {
+                            Ok(group) => group,
+                            Err(_) => {
+                                self.report(token.0, ObjErrorKind::InvalidNumber(token.1.to_string()))?;
+                                0
+                            },
+                        },
+                    };
+                },
+                "usemtl" => {
+                    let name = entry_data.iter().map(|t| t.1).collect::<Vec<&str>>().join(" ");
+                    current_material = match obj.materials.iter().position(|m| m.name == name) {
+                        Some(index) => Some(index),
+                        None => {
+                            obj.materials.push(Material::new(&name));
+                            Some(obj.materials.len() - 1)
+                        },
+                    };
                 },
                 _ => {
                    
                 }
             }
         }
-        return obj;
+
+        self.validate_faces(&mut obj, face_sources)?;
+        Ok(obj)
+    }
+
+    pub fn load_obj(file_as_string: String) -> Result<Object, ObjError> {
+        ObjLoader::new(false).load(&file_as_string)
+    }
+
+    // never fails, everything that would have been an error is returned as a warning
+    pub fn load_obj_lenient(file_as_string: String) -> (Object, Vec<ObjError>) {
+        let mut loader = ObjLoader::new(true);
+        let obj = match loader.load(&file_as_string) {
+            Ok(obj) => obj,
+            Err(error) => {
+                loader.warnings.push(error);
+                Object::new()
+            },
+        };
+        (obj, loader.warnings)
     }
 }
 
@@ -447,48 +1601,609 @@ impl ObjLoader {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("v  1.0\t2"), vec![(1, "v"), (4, "1.0"), (8, "2")]);
+        assert_eq!(tokenize("   "), vec![]);
+    }
+
     #[test]
     fn test_parse_point() {
-        let valid_data = ["0.1", "0.2"];
-        assert_eq!(ObjLoader::parse_point(&valid_data), Some(math::Point{x: 0.1, y: 0.2}));
+        let mut loader = ObjLoader::new(false);
+        let valid_data = [(4, "0.1"), (8, "0.2")];
+        assert_eq!(loader.parse_point(1, &valid_data), Ok(Some(math::Point{x: 0.1, y: 0.2})));
+
+        let missing_data = [(4, "0.1")];
+        assert_eq!(loader.parse_point(1, &missing_data).unwrap_err().kind, ObjErrorKind::MissingValues { expected: 2, found: 1 });
+
+        let invalid_data = [(4, "0.1"), (8, "zzz")];
+        let error = loader.parse_point(1, &invalid_data).unwrap_err();
+        assert_eq!(error.column, 8);
+        assert_eq!(error.kind, ObjErrorKind::InvalidNumber("zzz".to_string()));
+
+        let mut lenient = ObjLoader::new(true);
+        assert_eq!(lenient.parse_point(1, &missing_data), Ok(None));
+        assert_eq!(lenient.parse_point(1, &invalid_data), Ok(Some(math::Point{x: 0.1, y: 0.0})));
+        assert_eq!(lenient.warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_vertex() {
+        let mut loader = ObjLoader::new(false);
+        let valid_data = [(3, "0.1"), (7, "0.2"), (11, "0.3")];
+        assert_eq!(loader.parse_vertex(1, &valid_data), Ok(Some(math::Vector4{x: 0.1, y: 0.2, z: 0.3, w: 1.0})));
+
+        let missing_data = [(3, "0.1")];
+        assert!(loader.parse_vertex(1, &missing_data).is_err());
 
-        let missing_data = ["0.1"];
-        assert_eq!(ObjLoader::parse_point(&missing_data), None);
+        let invalid_data = [(3, "0.1"), (7, "zzz"), (11, "0.3")];
+        assert_eq!(loader.parse_vertex(1, &invalid_data).unwrap_err().column, 7);
 
-        let invalid_data = ["0.1", "zzz"];
-        assert_eq!(ObjLoader::parse_point(&invalid_data), Some(math::Point{x: 0.1, y: 0.0}));
+        let extra_data_is_ignored = [(3, "0.1"), (7, "0.2"), (11, "0.3"), (15, "0.4")];
+        assert_eq!(loader.parse_vertex(1, &extra_data_is_ignored), Ok(Some(math::Vector4{x: 0.1, y: 0.2, z: 0.3, w: 1.0})));
+
+        let mut lenient = ObjLoader::new(true);
+        assert_eq!(lenient.parse_vertex(1, &missing_data), Ok(None));
+        assert_eq!(lenient.parse_vertex(1, &invalid_data), Ok(Some(math::Vector4{x: 0.1, y: 0.0, z: 0.3, w: 1.0})));
     }
 
     #[test]
-    fn test_parse_vertex() {
-        let valid_data = ["0.1", "0.2", "0.3"];
-        assert_eq!(ObjLoader::parse_vertex(&valid_data), Some(math::Vector4{x: 0.1, y: 0.2, z: 0.3, w: 1.0}));
+    fn test_transform_matrix() {
+        let mut transform = Transform::new();
+        transform.position = math::Vector3 { x: 1.0, y: 2.0, z: 3.0 };
+        transform.scale = math::Vector3 { x: 2.0, y: 2.0, z: 2.0 };
 
-        let missing_data = ["0.1"];
-        assert_eq!(ObjLoader::parse_vertex(&missing_data), None);
+        let v = math::Vector4 { x: 1.0, y: 1.0, z: 1.0, w: 1.0 }.multiply(&transform.matrix());
+        assert_eq!(v, math::Vector4 { x: 3.0, y: 4.0, z: 5.0, w: 1.0 });
+    }
 
-        let invalid_data = ["0.1", "zzz", "0.3"];
-        assert_eq!(ObjLoader::parse_vertex(&invalid_data), Some(math::Vector4{x: 0.1, y: 0.0, z: 0.3, w: 1.0}));
+    #[test]
+    fn test_transform_from_matrix() {
+        let mut transform = Transform::new();
+        transform.position = math::Vector3 { x: 1.0, y: -2.0, z: 3.0 };
+        transform.rotation = math::Vector3 { x: 0.3, y: -1.1, z: 2.0 };
+        transform.scale = math::Vector3 { x: 2.0, y: 0.5, z: 1.0 };
+
+        let m = transform.matrix();
+        let decomposed = Transform::from_matrix(&m).matrix();
+        for row in 0..4 {
+            for col in 0..4 {
+                assert!((m.m[row][col] - decomposed.m[row][col]).abs() < 1e-5);
+            }
+        }
+    }
 
-        let extra_data_is_ignored = ["0.1", "0.2", "0.3", "0.4"];
-        assert_eq!(ObjLoader::parse_vertex(&extra_data_is_ignored), Some(math::Vector4{x: 0.1, y: 0.2, z: 0.3, w: 1.0}));
+    #[test]
+    fn test_world_matrices_compose_parent() {
+        let mut scene = Scene::new();
+        let parent = scene.add_object(Object::new());
+        let child = scene.add_object(Object::new());
+        scene.objects[parent].transform.position = math::Vector3 { x: 10.0, y: 0.0, z: 0.0 };
+        scene.objects[parent].transform.scale = math::Vector3 { x: 2.0, y: 2.0, z: 2.0 };
+        scene.objects[child].transform.position = math::Vector3 { x: 1.0, y: 0.0, z: 0.0 };
+        assert!(scene.set_parent(child, Some(parent)));
+
+        let world = world_matrices(&scene);
+        let v = math::Vector4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }.multiply(&world[child]);
+        assert_eq!(v, math::Vector4 { x: 12.0, y: 0.0, z: 0.0, w: 1.0 });
+    }
+
+    #[test]
+    fn test_set_parent_rejects_cycles() {
+        let mut scene = Scene::new();
+        let a = scene.add_object(Object::new());
+        let b = scene.add_object(Object::new());
+        assert!(scene.set_parent(b, Some(a)));
+        assert!(!scene.set_parent(a, Some(b)));
+        assert!(!scene.set_parent(a, Some(a)));
+        assert!(!scene.set_parent(a, Some(5)));
+        assert!(scene.set_parent(b, None));
+        assert!(scene.set_parent(a, Some(b)));
+    }
+
+    #[test]
+    fn test_object_store_handles_are_stable() {
+        let mut scene = Scene::new();
+        let a = scene.add_object(Object::new());
+        let b = scene.add_object(Object::new());
+        assert!(scene.set_parent(b, Some(a)));
+
+        assert!(scene.remove_object(a).is_some());
+        assert!(scene.remove_object(a).is_none());
+        assert_eq!(scene.objects[b].parent, None);
+
+        let c = scene.add_object(Object::new());
+        assert_ne!(c, a);
+        assert!(scene.objects.get(a).is_none());
+        assert_eq!(scene.objects.iter().map(|(handle, _)| handle).collect::<Vec<usize>>(), vec![b, c]);
+        assert_eq!(world_matrices(&scene).len(), 3);
+    }
+
+    #[test]
+    fn test_visibility_is_inherited() {
+        let mut scene = Scene::new();
+        let parent = scene.add_object(Object::new());
+        let child = scene.add_object(Object::new());
+        assert!(scene.set_parent(child, Some(parent)));
+        assert!(scene.is_visible(child));
+
+        scene.objects[parent].visible = false;
+        assert!(!scene.is_visible(parent));
+        assert!(!scene.is_visible(child));
+        assert!(!scene.is_visible(7));
+    }
+
+    #[test]
+    fn test_directional_light() {
+        let light = Light::directional(math::Vector3 { x: 0.0, y: -1.0, z: 0.0 }, math::Vector3 { x: 1.0, y: 1.0, z: 1.0 }, 1.0);
+        let position = math::Vector3::new();
+        let up = math::Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+        let down = math::Vector3 { x: 0.0, y: -1.0, z: 0.0 };
+
+        let (diffuse, specular) = light.illuminate(&position, &up, &up, 16.0);
+        assert_eq!(diffuse, 1.0);
+        assert_eq!(specular, 1.0);
+
+        assert_eq!(light.illuminate(&position, &down, &up, 16.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_point_light_range() {
+        let light = Light::point(math::Vector3 { x: 0.0, y: 2.0, z: 0.0 }, math::Vector3 { x: 1.0, y: 1.0, z: 1.0 }, 1.0, 4.0);
+        let up = math::Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+
+        let (near, _) = light.illuminate(&math::Vector3::new(), &up, &up, 0.0);
+        assert_eq!(near, 0.5625);
+
+        let (far, _) = light.illuminate(&math::Vector3 { x: 0.0, y: -3.0, z: 0.0 }, &up, &up, 0.0);
+        assert_eq!(far, 0.0);
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        let light = Light::spot(
+            math::Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+            math::Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+            math::Vector3 { x: 1.0, y: 1.0, z: 1.0 },
+            1.0, 0.0, 0.2, 0.4);
+        let up = math::Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+
+        let (inside, _) = light.illuminate(&math::Vector3::new(), &up, &up, 0.0);
+        assert_eq!(inside, 1.0);
+
+        let (outside, _) = light.illuminate(&math::Vector3 { x: 1.0, y: 0.0, z: 0.0 }, &up, &up, 0.0);
+        assert_eq!(outside, 0.0);
+    }
+
+    #[test]
+    fn test_shade_adds_ambient() {
+        let lights = vec![Light::directional(math::Vector3 { x: 0.0, y: -1.0, z: 0.0 }, math::Vector3 { x: 1.0, y: 0.5, z: 0.0 }, 0.5)];
+        let ambient = math::Vector3 { x: 0.1, y: 0.1, z: 0.1 };
+        let up = math::Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+        let eye = math::Vector3 { x: 0.0, y: 5.0, z: 0.0 };
+
+        let (diffuse, specular) = shade(&lights, &ambient, &math::Vector3::new(), &up, &eye, &math::Vector3::new(), 8.0);
+        assert_eq!(diffuse, math::Vector3 { x: 0.6, y: 0.35, z: 0.1 });
+        assert_eq!(specular, math::Vector3::new());
+    }
+
+    #[test]
+    fn test_load_obj_materials() {
+        let obj_data = "mtllib two materials.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+usemtl red
+f 1 2 3
+usemtl blue
+f 1 2 3
+usemtl red
+f 1 2 3
+";
+        let mut obj = ObjLoader::load_obj(obj_data.to_string()).unwrap();
+        assert_eq!(obj.material_libs, vec!["two materials.mtl".to_string()]);
+        assert_eq!(obj.materials.len(), 2);
+        assert_eq!(obj.faces.iter().map(|f| f.material).collect::<Vec<Option<usize>>>(), vec![Some(0), Some(1), Some(0)]);
+
+        let mtl = "newmtl red\nmap_Kd red.png\nnewmtl unused\n";
+        assert_eq!(obj.apply_materials(crate::render::material::MtlLoader::load_mtl(mtl)), 1);
+        assert!(obj.materials[0].defined);
+        assert!(!obj.materials[1].defined);
+        assert_eq!(obj.missing_textures(), vec!["red.png".to_string()]);
+
+        assert_eq!(obj.set_texture_file("red.png", &crate::render::Texture::from_rgba(vec![255, 0, 0, 255], 1, 1)), 1);
+        assert!(obj.missing_textures().is_empty());
+    }
+
+    fn uv_test_object() -> Object {
+        ObjLoader::load_obj("v -1 0 -1
+v 1 0 -1
+v 1 2 1
+v -1 0 1
+vt 0.25 0.75
+f 1 2 3
+f 1/1 2/1 4/1
+".to_string()).unwrap()
+    }
+
+    // two triangles folded 90 degrees along the z axis edge, x = 0 to 1
+    fn folded_object(obj_extra: &str) -> Object {
+        ObjLoader::load_obj(format!("v 0 0 0
+v 0 0 1
+v 1 0 0
+v 0 1 0
+{}", obj_extra)).unwrap()
+    }
+
+    #[test]
+    fn test_generate_normals_crease_angle() {
+        let mut obj = folded_object("f 1 2 3\nf 1 4 2\n");
+
+        obj.generate_normals(crate::render::NormalGeneration::Flat, std::f32::consts::PI);
+        assert_eq!(obj.vertex_normals.len(), 2);
+
+        obj.generate_normals(crate::render::NormalGeneration::Smooth, std::f32::consts::PI);
+        // the two corners on the shared edge are averaged, the others keep their face normal
+        assert_eq!(obj.vertex_normals.len(), 3);
+        let shared = &obj.vertex_normals[obj.faces[0].vn2 as usize];
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((shared.x.abs() - half).abs() < 1e-6 && (shared.y.abs() - half).abs() < 1e-6);
+
+        // 90 degrees is over a 60 degree crease
+        obj.generate_normals(crate::render::NormalGeneration::Smooth, 60f32.to_radians());
+        assert_eq!(obj.vertex_normals.len(), 2);
+    }
+
+    #[test]
+    fn test_generate_normals_smoothing_groups() {
+        let mut obj = folded_object("s 1\nf 1 2 3\ns off\nf 1 4 2\n");
+        assert_eq!((obj.faces[0].smoothing_group, obj.faces[1].smoothing_group), (1, 0));
+        obj.generate_normals(crate::render::NormalGeneration::SmoothingGroups, std::f32::consts::PI);
+        assert_eq!(obj.vertex_normals.len(), 2);
+
+        let mut obj = folded_object("s 2\nf 1 2 3\nf 1 4 2\n");
+        obj.generate_normals(crate::render::NormalGeneration::SmoothingGroups, std::f32::consts::PI);
+        assert_eq!(obj.vertex_normals.len(), 3);
+
+        assert!(ObjLoader::load_obj("s x\n".to_string()).is_err());
+    }
+
+    fn world_matrices(scene: &Scene) -> Vec<math::Matrix4> {
+        let mut world_matrices = vec![];
+        scene.world_matrices_into(&mut vec![], &mut world_matrices);
+        world_matrices
+    }
+
+    fn draw_order(scene: &Scene) -> Vec<usize> {
+        let mut order = vec![];
+        scene.draw_order_into(&world_matrices(scene), &mut order, &mut vec![]);
+        order
+    }
+
+    fn target(width: u32, height: u32) -> crate::render::RenderTarget {
+        crate::render::RenderTarget { width, height, buffer: vec![0; (width * height * 4) as usize], depth: vec![1.0; (width * height) as usize] }
+    }
+
+    #[test]
+    fn test_cull_mode() {
+        let mut obj = ObjLoader::load_obj("v -1 -1 -2\nv 1 -1 -2\nv 0 1 -2\nf 1 2 3\n".to_string()).unwrap();
+        obj.texture = crate::render::Texture::blank();
+        let mut scene = Scene::new();
+        let handle = scene.add_object(obj);
+
+        // counter clockwise towards the camera is the front
+        let center_drawn = |scene: &Scene| {
+            let mut render_target = target(9, 9);
+            scene.draw(&mut render_target, &mut DrawScratch::default());
+            render_target.buffer[(4 * 9 + 4) * 4] == 255
+        };
+        assert!(center_drawn(&scene));
+        scene.objects[handle].cull_mode = crate::render::CullMode::Front;
+        assert!(!center_drawn(&scene));
+
+        scene.objects[handle].flip_winding();
+        assert_eq!(scene.objects[handle].faces[0].v0, 0);
+        assert!(center_drawn(&scene));
+        scene.objects[handle].cull_mode = crate::render::CullMode::Back;
+        assert!(!center_drawn(&scene));
+        scene.objects[handle].cull_mode = crate::render::CullMode::None;
+        assert!(center_drawn(&scene));
+    }
+
+    #[test]
+    fn test_draw_frustum_culling() {
+        let triangle = || {
+            let mut obj = ObjLoader::load_obj("v -1 -1 0\nv 1 -1 0\nv 0 1 0\nf 1 2 3\n".to_string()).unwrap();
+            obj.texture = crate::render::Texture::blank();
+            obj
+        };
+        let mut scene = Scene::new();
+        let ahead = scene.add_object(triangle());
+        let behind = scene.add_object(triangle());
+        scene.objects[ahead].transform.position.z = -3.0;
+        scene.objects[behind].transform.position.z = 5.0;
+        assert_eq!(scene.objects[ahead].bounding_sphere, math::Sphere { center: math::Vector3::new(), radius: 2.0f32.sqrt() });
+
+        let stats = scene.draw(&mut target(4, 4), &mut DrawScratch::default());
+        assert_eq!(stats, FrameStats { objects_drawn: 1, objects_culled: 1, triangles_drawn: 1 });
+    }
+
+    #[test]
+    fn test_draw_screen_covering_triangle() {
+        // every corner is off screen diagonally, but the triangle covers the screen
+        let mut obj = ObjLoader::load_obj("v -30 -20 -10\nv 20 -20 -10\nv 20 30 -10\nf 1 2 3\n".to_string()).unwrap();
+        obj.texture = crate::render::Texture::blank();
+        let mut scene = Scene::new();
+        scene.add_object(obj);
+
+        let mut render_target = target(9, 9);
+        let stats = scene.draw(&mut render_target, &mut DrawScratch::default());
+        assert!(stats.triangles_drawn > 0);
+        assert_eq!(render_target.buffer[(4 * 9 + 4) * 4], 255);
+    }
+
+    #[test]
+    fn test_draw_order() {
+        let mut scene = Scene::new();
+        let at = |z: f32, blend_mode| {
+            let mut obj = Object::new();
+            obj.transform.position.z = z;
+            obj.blend_mode = blend_mode;
+            obj
+        };
+        let near_glass = scene.add_object(at(-1.0, crate::render::BlendMode::AlphaBlend));
+        let wall = scene.add_object(at(-5.0, crate::render::BlendMode::Opaque));
+        let far_glow = scene.add_object(at(-3.0, crate::render::BlendMode::Additive));
+        let hidden = scene.add_object(at(-4.0, crate::render::BlendMode::Multiply));
+        scene.objects[hidden].visible = false;
+
+        assert_eq!(draw_order(&scene), vec![wall, far_glow, near_glass]);
+    }
+
+    #[test]
+    fn test_draw_background() {
+        let mut scene = Scene::new();
+        let mut render_target = target(2, 3);
+        scene.draw_background(&mut render_target);
+        assert_eq!(&render_target.buffer[..8], &[0, 0, 0, 255, 0, 0, 0, 255]);
+
+        scene.background = Background::Gradient { top: math::Vector3 { x: 1.0, y: 0.0, z: 0.0 }, bottom: math::Vector3 { x: 0.0, y: 0.0, z: 1.0 } };
+        scene.draw_background(&mut render_target);
+        assert_eq!(&render_target.buffer[..4], &[255, 0, 0, 255]);
+        assert_eq!(&render_target.buffer[8..12], &[128, 0, 128, 255]);
+        assert_eq!(&render_target.buffer[20..24], &[0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_draw_background_skybox() {
+        let mut faces = vec![];
+        for face in 0..6u8 {
+            faces.extend_from_slice(&[face * 40, 0, 0, 255]);
+        }
+        let mut scene = Scene::new();
+        scene.background = Background::Skybox(scene.add_cube_map(crate::render::cubemap::CubeMap::from_faces(&faces, 1).unwrap()));
+        let mut render_target = target(3, 3);
+
+        // looking down -z, then +x
+        scene.draw_background(&mut render_target);
+        assert_eq!(render_target.buffer[16], 200);
+        scene.camera.look_at(&math::Vector3 { x: 5.0, y: 0.0, z: 1.0 });
+        scene.draw_background(&mut render_target);
+        assert_eq!(render_target.buffer[16], 0);
+    }
+
+    #[test]
+    fn test_camera_orientation() {
+        let close = |a: math::Vector3, b: math::Vector3| a.sub(&b).len() < 1e-5;
+        let up = math::Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+
+        // the default camera sits on +z looking at the origin
+        let mut camera = Camera::new();
+        let (right, camera_up, forward) = camera.axes();
+        assert!(close(forward, math::Vector3 { x: 0.0, y: 0.0, z: -1.0 }) && close(camera_up, up));
+        assert!(close(right, math::Vector3 { x: -1.0, y: 0.0, z: 0.0 }));
+        let origin = math::Vector4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }.multiply(&camera.view_matrix());
+        assert!(close(math::Vector3 { x: origin.x, y: origin.y, z: origin.z }, math::Vector3 { x: 0.0, y: 0.0, z: 1.0 }));
+
+        // passing over the top keeps the image upright instead of spinning
+        camera.position = math::Vector3 { x: 0.0, y: 0.0, z: 0.01 };
+        camera.look_at(&math::Vector3 { x: 0.0, y: 5.0, z: 0.0 });
+        let before = camera.axes().0;
+        camera.position = math::Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+        camera.look_at(&math::Vector3 { x: 0.0, y: 5.0, z: 0.0 });
+        assert!(close(camera.axes().0, before));
+
+        // rolled a quarter turn the right side points up, and stays there when re-aimed
+        let mut camera = Camera::new();
+        camera.roll(std::f32::consts::FRAC_PI_2);
+        assert!(close(camera.axes().0, up));
+        camera.position = math::Vector3 { x: 0.0, y: 0.0, z: 3.0 };
+        camera.look_at(&math::Vector3::new());
+        assert!(close(camera.axes().0, up));
+    }
+
+    #[test]
+    fn test_camera_projection() {
+        let mut camera = Camera::new();
+        camera.near = 0.5;
+        camera.far = 20.0;
+        camera.orthographic_height = 6.0;
+        camera.window = [-0.1, 0.4, 0.0, 0.3];
+        for &mode in [crate::render::ProjectionMode::Perspective, crate::render::ProjectionMode::Orthographic, crate::render::ProjectionMode::OffAxis].iter() {
+            camera.projection = mode;
+            let matrix = camera.projection_matrix(1.5);
+            for &(x, y) in [(0.0, 0.0), (-1.0, 1.0), (0.5, -0.25)].iter() {
+                // anywhere along the ray lands back on the same pixel, y flips to point down
+                let ray = camera.view_ray(x, y, 1.5);
+                let offset = match mode {
+                    crate::render::ProjectionMode::Orthographic => math::Vector3 { x: x * 4.5, y: y * 3.0, z: 0.0 },
+                    _ => math::Vector3::new(),
+                };
+                let p = offset.add(&ray.scale(7.0));
+                let clip = math::Vector4 { x: p.x, y: p.y, z: p.z, w: 1.0 }.multiply(&matrix);
+                assert!((clip.x / clip.w - x).abs() < 1e-5 && (clip.y / clip.w + y).abs() < 1e-5, "{:?} {} {}", mode, x, y);
+                assert!(clip.z > 0.0 && clip.z < clip.w);
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_tangents() {
+        let mut obj = ObjLoader::load_obj("v 0 0 0
+v 2 0 0
+v 2 2 0
+v 0 2 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 4/4/1
+f 1 2 3
+".to_string()).unwrap();
+        obj.generate_tangents();
+        assert_eq!(obj.tangents.len(), 3);
+        // tangent follows +u, bitangent n x t follows +v
+        for t in obj.tangents[0].iter().chain(obj.tangents[1].iter()) {
+            assert_eq!(*t, math::Vector4 { x: 1.0, y: 0.0, z: 0.0, w: 1.0 });
+        }
+        // mirrored v flips the sign
+        obj.uvs.iter_mut().for_each(|uv| uv.y = 1.0 - uv.y);
+        obj.generate_tangents();
+        assert_eq!(obj.tangents[0][0].w, -1.0);
+        // no uvs, no tangent
+        assert_eq!(obj.tangents[2][0], math::Vector4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 });
+    }
+
+    #[test]
+    fn test_texture_coordinates_mesh_falls_back_to_planar() {
+        let mut obj = uv_test_object();
+        obj.uv_source = crate::render::UvSource::Mesh;
+        obj.update_bounds();
+
+        let uvs = texture_coordinates(&obj, &obj.faces[1], &[], &obj.bounds);
+        assert_eq!(uvs[0], math::Point { x: 0.25, y: 0.25 });
+
+        // the first face has no vt and there are no normals
+        let uvs = texture_coordinates(&obj, &obj.faces[0], &[], &obj.bounds);
+        assert_eq!(uvs, [
+            math::Point { x: 1.0, y: 0.0 },
+            math::Point { x: 1.0, y: 1.0 },
+            math::Point { x: 0.0, y: 1.0 },
+        ]);
+    }
+
+    #[test]
+    fn test_texture_coordinates_cylindrical_seam() {
+        let mut obj = ObjLoader::load_obj("v -1 0 -0.1
+v -1 0 0.1
+v -1 1 0
+v 1 0 0
+f 1 2 3
+".to_string()).unwrap();
+        obj.uv_source = crate::render::UvSource::Cylindrical;
+        obj.update_bounds();
+
+        // the face sits on the -x side where atan2 wraps around
+        let uvs = texture_coordinates(&obj, &obj.faces[0], &[], &obj.bounds);
+        let min_u = uvs.iter().fold(f32::MAX, |m, p| m.min(p.x));
+        let max_u = uvs.iter().fold(f32::MIN, |m, p| m.max(p.x));
+        assert!(max_u - min_u < 0.1);
     }
 
     #[test]
     fn test_parse_face_indexes() {
-        let valid_data = "1/2/3";
-        assert_eq!(ObjLoader::parse_face_indexes(&valid_data), (1, 2, 3));
+        let mut loader = ObjLoader::new(false);
+        assert_eq!(loader.parse_face_indexes((3, "1/2/3")), Ok((1, 2, 3)));
+        assert_eq!(loader.parse_face_indexes((3, "1//3")), Ok((1, 0, 3)));
+        assert_eq!(loader.parse_face_indexes((3, "1")), Ok((1, 0, 0)));
+        assert_eq!(loader.parse_face_indexes((3, "1/2")), Ok((1, 2, 0)));
+
+        let error = loader.parse_face_indexes((3, "1/y/3")).unwrap_err();
+        assert_eq!(error.column, 5);
+        assert_eq!(error.kind, ObjErrorKind::InvalidNumber("y".to_string()));
+
+        let mut lenient = ObjLoader::new(true);
+        assert_eq!(lenient.parse_face_indexes((3, "x/y/z")), Ok((0, 0, 0)));
+        assert_eq!(lenient.warnings.len(), 3);
+    }
 
-        let missing_uv = "1//3";
-        assert_eq!(ObjLoader::parse_face_indexes(&missing_uv), (1, 0, 3));
+    #[test]
+    fn test_resolve_index() {
+        assert_eq!(resolve_index(2, 5), 2);
+        assert_eq!(resolve_index(-1, 5), 5);
+        assert_eq!(resolve_index(-5, 5), 1);
+        assert_eq!(resolve_index(0, 5), 0);
+    }
 
-        let invalid_data = "x/y/z";
-        assert_eq!(ObjLoader::parse_face_indexes(&invalid_data), (0, 0, 0));
+    #[test]
+    fn test_triangulate_concave_polygon() {
+        let v = |x: f32, y: f32| math::Vector4 { x, y, z: 0.0, w: 1.0 };
+        // an arrow head pointing up, corner 3 is the reflex one
+        let polygon = [v(0.0, 0.0), v(1.0, 2.0), v(2.0, 0.0), v(1.0, 1.0)];
+        let triangles = triangulate_polygon(&polygon);
+        assert_eq!(triangles.len(), 2);
+        for t in triangles.iter() {
+            let (a, b, c) = (&polygon[t[0]], &polygon[t[1]], &polygon[t[2]]);
+            // every triangle keeps the clockwise winding of the polygon
+            assert!(area2((a.x, a.y), (b.x, b.y), (c.x, c.y)) < 0.0);
+        }
+        // the reflex corner can't be an ear, so it is in both triangles
+        assert!(triangles.iter().all(|t| t.contains(&3)));
 
-        let only_vertex_index = "1";
-        assert_eq!(ObjLoader::parse_face_indexes(&only_vertex_index), (1, 0, 0));
+        // same polygon seen from the other side
+        let flipped: Vec<math::Vector4> = polygon.iter().map(|p| v(p.x, -p.y)).collect();
+        assert!(triangulate_polygon(&flipped).iter().all(|t| t.contains(&3)));
+    }
 
-        let no_vertex_normal = "1/2";
-        assert_eq!(ObjLoader::parse_face_indexes(&no_vertex_normal), (1, 2, 0));
+    #[test]
+    fn test_load_obj_negative_indexes_and_ngons() {
+        let obj_data = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0.5 1.5 0
+v 0 1 0
+vt 0 0
+f -5/-1 -4/-1 -3/-1 -2/-1 -1/-1
+";
+        let obj = ObjLoader::load_obj(obj_data.to_string()).unwrap();
+        assert_eq!(obj.faces.len(), 3);
+        assert!(obj.faces.iter().all(|f| f.uv0 == 0 && f.uv1 == 0 && f.uv2 == 0));
+
+        let mut corners: Vec<i32> = obj.faces.iter().flat_map(|f| vec![f.v0, f.v1, f.v2]).collect();
+        corners.sort();
+        corners.dedup();
+        assert_eq!(corners, vec![0, 1, 2, 3, 4]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_load_obj_reports_line_and_column() {
+        let obj_data = "v 0 0 0
+v 1 0 0
+v 0 1 zz
+";
+        let error = ObjLoader::load_obj(obj_data.to_string()).unwrap_err();
+        assert_eq!(error, ObjError { line: 3, column: 7, kind: ObjErrorKind::InvalidNumber("zz".to_string()) });
+        assert_eq!(error.to_string(), "line 3, column 7: invalid number 'zz'");
+    }
+
+    #[test]
+    fn test_load_obj_validates_indexes() {
+        let obj_data = "v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+f 1 2/1 4
+";
+        let error = ObjLoader::load_obj(obj_data.to_string()).unwrap_err();
+        assert_eq!(error.line, 5);
+        assert_eq!(error.column, 9);
+        assert_eq!(error.kind, ObjErrorKind::IndexOutOfRange { element: "vertex", index: 4, count: 3 });
+
+        let (obj, warnings) = ObjLoader::load_obj_lenient(obj_data.to_string());
+        assert_eq!(obj.faces.len(), 1);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[1].column, 5);
+        assert_eq!(warnings[1].kind, ObjErrorKind::IndexOutOfRange { element: "texture coordinate", index: 1, count: 0 });
+    }
+}
@@ -0,0 +1,240 @@
+use std::fmt;
+use crate::math;
+use super::scene::{Face, Object};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StlError {
+    // binary file shorter than its triangle count says
+    Truncated { expected: usize, found: usize },
+    // ascii file, 1-based line
+    Parse { line: usize, reason: String },
+}
+
+impl fmt::Display for StlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StlError::Truncated { expected, found } => write!(f, "truncated stl, expected {} bytes, found {}", expected, found),
+            StlError::Parse { line, reason } => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+pub struct StlLoader {
+
+}
+
+impl StlLoader {
+    // binary files can start with "solid" too, the size check is what tells them apart
+    pub fn is_binary(data: &[u8]) -> bool {
+        StlLoader::binary_size(data) == Some(data.len())
+    }
+
+    // header, count and 50 bytes per triangle. none if the count in the file
+    // doesn't fit in memory, which on wasm32 it can easily
+    fn binary_size(data: &[u8]) -> Option<usize> {
+        if data.len() < 84 {
+            return None;
+        }
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        count.checked_mul(50).and_then(|n| n.checked_add(84))
+    }
+
+    pub fn is_ascii(data: &[u8]) -> bool {
+        let start = &data[..data.len().min(512)];
+        let text = String::from_utf8_lossy(start);
+        text.trim_start().starts_with("solid") && !StlLoader::is_binary(data)
+    }
+
+    // stl has no shared vertices, every facet gets its own three. the stored
+    // normal is often zero or just wrong, normals are generated from the winding
+    fn add_facet(obj: &mut Object, corners: [math::Vector3; 3], color: Option<math::Vector3>) {
+        let base = obj.vertices.len() as i32;
+        for c in corners.iter() {
+            obj.vertices.push(math::Vector4 { x: c.x, y: c.y, z: c.z, w: 1.0 });
+            if let Some(color) = color {
+                obj.colors.push(color);
+            }
+        }
+
+        // same reversed winding as ObjLoader
+        obj.faces.push(Face {
+            v0: base + 2,
+            v1: base + 1,
+            v2: base,
+            vn0: -1,
+            vn1: -1,
+            vn2: -1,
+            uv0: -1,
+            uv1: -1,
+            uv2: -1,
+            material: None,
+            smoothing_group: 0,
+        });
+    }
+
+    fn read_vector(data: &[u8], offset: usize) -> math::Vector3 {
+        let f = |i: usize| {
+            let at = offset + i * 4;
+            f32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+        };
+        math::Vector3 { x: f(0), y: f(1), z: f(2) }
+    }
+
+    // the attribute word holds a 15 bit color when its top bit is set (visCAM / solidView)
+    fn facet_color(attribute: u16) -> Option<math::Vector3> {
+        if attribute & 0x8000 == 0 {
+            return None;
+        }
+        let channel = |shift: u16| ((attribute >> shift) & 0x1F) as f32 / 31.0;
+        Some(math::Vector3 { x: channel(10), y: channel(5), z: channel(0) })
+    }
+
+    pub fn load_binary(data: &[u8]) -> Result<Object, StlError> {
+        if data.len() < 84 {
+            return Err(StlError::Truncated { expected: 84, found: data.len() });
+        }
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        let expected = StlLoader::binary_size(data).unwrap_or(usize::MAX);
+        if data.len() < expected {
+            return Err(StlError::Truncated { expected, found: data.len() });
+        }
+
+        let mut obj = Object::new();
+        let colors: Vec<Option<math::Vector3>> = (0..count)
+            .map(|i| {
+                let at = 84 + i * 50 + 48;
+                StlLoader::facet_color(u16::from_le_bytes([data[at], data[at + 1]]))
+            })
+            .collect();
+        // only use colors if every facet has one, vertex colors are all or nothing
+        let colored = count > 0 && colors.iter().all(|c| c.is_some());
+
+        for (i, color) in colors.into_iter().enumerate() {
+            let at = 84 + i * 50;
+            let corners = [
+                StlLoader::read_vector(data, at + 12),
+                StlLoader::read_vector(data, at + 24),
+                StlLoader::read_vector(data, at + 36),
+            ];
+            StlLoader::add_facet(&mut obj, corners, if colored { color } else { None });
+        }
+        obj.texture = super::Texture::blank();
+        Ok(obj)
+    }
+
+    fn parse_vector(parts: &[&str], line: usize) -> Result<math::Vector3, StlError> {
+        if parts.len() < 3 {
+            return Err(StlError::Parse { line, reason: format!("expected 3 values, found {}", parts.len()) });
+        }
+        let value = |i: usize| parts[i].parse::<f32>()
+            .map_err(|_| StlError::Parse { line, reason: format!("invalid number '{}'", parts[i]) });
+        Ok(math::Vector3 { x: value(0)?, y: value(1)?, z: value(2)? })
+    }
+
+    pub fn load_ascii(file_as_string: &str) -> Result<Object, StlError> {
+        let mut obj = Object::new();
+        let mut corners: Vec<math::Vector3> = Vec::with_capacity(3);
+
+        for (line_index, line) in file_as_string.lines().enumerate() {
+            let line_number = line_index + 1;
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.is_empty() {
+                continue;
+            }
+
+            match parts[0] {
+                "facet" => {
+                    // "facet normal nx ny nz", checked but not used
+                    StlLoader::parse_vector(parts.get(2..).unwrap_or(&[]), line_number)?;
+                    corners.clear();
+                },
+                "vertex" => {
+                    corners.push(StlLoader::parse_vector(&parts[1..], line_number)?);
+                },
+                "endfacet" => {
+                    if corners.len() != 3 {
+                        return Err(StlError::Parse { line: line_number, reason: format!("facet has {} vertices", corners.len()) });
+                    }
+                    StlLoader::add_facet(&mut obj, [corners[0], corners[1], corners[2]], None);
+                },
+                _ => {
+
+                }
+            }
+        }
+        obj.texture = super::Texture::blank();
+        Ok(obj)
+    }
+
+    pub fn load_stl(data: &[u8]) -> Result<Object, StlError> {
+        if StlLoader::is_binary(data) {
+            StlLoader::load_binary(data)
+        } else {
+            StlLoader::load_ascii(&String::from_utf8_lossy(data))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary_stl(color: u16) -> Vec<u8> {
+        let mut data = vec![0u8; 80];
+        data.extend_from_slice(&1u32.to_le_bytes());
+        let floats = [0.0f32, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        for f in floats.iter() {
+            data.extend_from_slice(&f.to_le_bytes());
+        }
+        data.extend_from_slice(&color.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_load_ascii_stl() {
+        let stl = "solid tri
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid tri
+";
+        assert!(StlLoader::is_ascii(stl.as_bytes()));
+        let obj = StlLoader::load_stl(stl.as_bytes()).unwrap();
+        assert_eq!(obj.vertices.len(), 3);
+        assert_eq!(obj.faces.len(), 1);
+        assert_eq!((obj.faces[0].v0, obj.faces[0].v1, obj.faces[0].v2), (2, 1, 0));
+        // normals are left to the renderer
+        assert!(obj.vertex_normals.is_empty());
+
+        let broken = "solid\nfacet normal 0 0 1\nvertex 0 0 zz\n";
+        assert_eq!(StlLoader::load_stl(broken.as_bytes()).err(), Some(StlError::Parse { line: 3, reason: "invalid number 'zz'".to_string() }));
+    }
+
+    #[test]
+    fn test_load_binary_stl() {
+        let data = binary_stl(0);
+        assert!(StlLoader::is_binary(&data));
+        let obj = StlLoader::load_stl(&data).unwrap();
+        assert_eq!(obj.vertices[1], math::Vector4 { x: 1.0, y: 0.0, z: 0.0, w: 1.0 });
+        assert!(obj.vertex_normals.is_empty());
+        assert!(obj.colors.is_empty());
+
+        // pure red
+        let colored = StlLoader::load_stl(&binary_stl(0x8000 | (31 << 10))).unwrap();
+        assert_eq!(colored.colors, vec![math::Vector3 { x: 1.0, y: 0.0, z: 0.0 }; 3]);
+
+        let truncated = &data[..100];
+        assert!(!StlLoader::is_binary(truncated));
+        assert_eq!(StlLoader::load_binary(truncated).err(), Some(StlError::Truncated { expected: 134, found: 100 }));
+
+        // a huge count is rejected rather than wrapping around
+        let mut huge = data.clone();
+        huge[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(!StlLoader::is_binary(&huge));
+        assert!(matches!(StlLoader::load_binary(&huge), Err(StlError::Truncated { .. })));
+    }
+}
@@ -0,0 +1,70 @@
+//! Frame time and allocation count of CanvasRenderer::render at 1024x768 with
+//! cats.obj. Run with `cargo test --release --test frame_allocations -- --nocapture`
+//! to see the timings.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::time::Instant;
+
+use canvas_wasm_test::CanvasRenderer;
+
+// counts allocations made by the current thread while enabled
+struct CountingAllocator;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.with(|c| c.get()) {
+            ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if COUNTING.with(|c| c.get()) {
+            ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_render_steady_state_allocations() {
+    let cats = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/www/models/cats.obj")).unwrap();
+    let mut renderer = CanvasRenderer::with_size(1024, 768);
+    renderer.add_obj(cats, vec![255; 4], 1, 1).unwrap();
+    renderer.add_directional_light(-1.0, -1.0, -1.0, 1.0, 1.0, 1.0, 0.8);
+    renderer.set_camera_position(0.0, 100.0, 400.0);
+    renderer.set_camera_target(0.0, 100.0, 0.0);
+
+    // the first frames grow the scratch buffers
+    for _ in 0..3 {
+        renderer.render();
+    }
+    assert!(renderer.frame_stats().triangles_drawn > 0);
+
+    let frames = 20;
+    ALLOCATIONS.with(|a| a.set(0));
+    COUNTING.with(|c| c.set(true));
+    let start = Instant::now();
+    for _ in 0..frames {
+        renderer.render();
+    }
+    let elapsed = start.elapsed();
+    COUNTING.with(|c| c.set(false));
+
+    let allocations = ALLOCATIONS.with(|a| a.get());
+    println!("{:.2} ms per frame, {} allocations in {} frames", elapsed.as_secs_f64() * 1000.0 / frames as f64, allocations, frames);
+    assert_eq!(allocations, 0);
+}
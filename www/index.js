@@ -1,34 +1,70 @@
 'use strict';
 
-import { CanvasRenderer } from 'canvas-wasm-test';
+import { CameraControl, CanvasRenderer, ShadingMode, TextureFilter } from 'canvas-wasm-test';
 import { memory } from 'canvas-wasm-test/canvas_wasm_test_bg';
 
-const renderer = CanvasRenderer.new();
-const w = renderer.width();
-const h = renderer.height();
-
 const canvas = document.getElementById('canvas');
-canvas.width = w;
-canvas.height = h;
+const renderer = CanvasRenderer.with_size(canvas.clientWidth || 1024, canvas.clientHeight || 768);
+
+// the buffer follows the element's css size at the device pixel ratio
+const resize = () => {
+    renderer.resize(canvas.clientWidth || 1024, canvas.clientHeight || 768, window.devicePixelRatio || 1);
+    canvas.width = renderer.width();
+    canvas.height = renderer.height();
+};
+resize();
+window.addEventListener('resize', resize);
 
 const ctx = canvas.getContext('2d');
 
-let frame = 0;
+// the renderer moves the camera from the raw input events
+renderer.set_camera_position(2.5, 1.5, 0);
+renderer.set_camera_target(0, 0, 0);
+renderer.set_camera_control(CameraControl.Orbit);
+
+canvas.tabIndex = 0;
+canvas.style.touchAction = 'none';
+canvas.addEventListener('contextmenu', (e) => e.preventDefault());
+canvas.addEventListener('pointerdown', (e) => {
+    if (e.pointerType === 'touch') return;
+    canvas.setPointerCapture(e.pointerId);
+    renderer.pointer_down(e.offsetX, e.offsetY, e.button);
+});
+canvas.addEventListener('pointermove', (e) => {
+    if (e.pointerType !== 'touch') renderer.pointer_move(e.offsetX, e.offsetY);
+});
+canvas.addEventListener('pointerup', (e) => {
+    if (e.pointerType !== 'touch') renderer.pointer_up(e.button);
+});
+canvas.addEventListener('wheel', (e) => {
+    e.preventDefault();
+    renderer.wheel(e.deltaY);
+}, { passive: false });
+
+const touches = (e, handle) => {
+    e.preventDefault();
+    const rect = canvas.getBoundingClientRect();
+    for (const t of e.changedTouches) {
+        handle(t.identifier, t.clientX - rect.left, t.clientY - rect.top);
+    }
+};
+canvas.addEventListener('touchstart', (e) => touches(e, (id, x, y) => renderer.touch_start(id, x, y)), { passive: false });
+canvas.addEventListener('touchmove', (e) => touches(e, (id, x, y) => renderer.touch_move(id, x, y)), { passive: false });
+canvas.addEventListener('touchend', (e) => touches(e, (id) => renderer.touch_end(id)), { passive: false });
+canvas.addEventListener('touchcancel', (e) => touches(e, (id) => renderer.touch_end(id)), { passive: false });
+canvas.addEventListener('keydown', (e) => renderer.key_down(e.code));
+canvas.addEventListener('keyup', (e) => renderer.key_up(e.code));
+
+let last = performance.now();
 
 const loop = () => {
-    // renderer.setCameraPosition(
-    //     Math.cos(2 * Math.PI * frame / 512) * 250,
-    //     150 + Math.sin(2 * Math.PI * frame / 512),
-    //     Math.sin(2 * Math.PI * frame / 512) * 250);
-    // renderer.setCameraTarget(0, 100, 0);
-    renderer.set_camera_position(
-        Math.cos(2 * Math.PI * frame / 512) * 2.5,
-        1.50 + Math.sin(2 * Math.PI * frame / 512),
-        Math.sin(2 * Math.PI * frame / 300) * 2.50);
-    renderer.set_camera_target(0, 0, 0);
+    const now = performance.now();
+    renderer.update_controls(Math.min(now - last, 100) / 1000);
+    last = now;
     renderer.render();
-    frame++;
 
+    const w = renderer.width();
+    const h = renderer.height();
     const bufferPtr = renderer.buffer();
     const buffer = new Uint8ClampedArray(memory.buffer, bufferPtr, w * h * 4);
     const imageData = new ImageData(buffer, w, h);
@@ -61,7 +97,10 @@ const rustTexture = new Promise((resolve, reject) => {
 const objs = [rustTexture, torusObject, cubeObject];
 
 Promise.all(objs).then((values) => {
-    renderer.add_obj(values[1], values[0].data, values[0].width, values[0].height);
+    const torus = renderer.add_obj(values[1], values[0].data, values[0].width, values[0].height);
+    renderer.set_texture_filter(torus, TextureFilter.Trilinear);
+    renderer.set_object_shading(torus, ShadingMode.Phong);
+    renderer.add_directional_light(-1, -1, -1, 1, 1, 1, 0.8);
     //renderer.add_obj(values[2], values[0].data, values[0].width, values[0].height);
     loop();
 }).catch((reason) => {